@@ -29,8 +29,18 @@ pub(crate) async fn channel(
 
     let (channel_tx, channel_rx) = oneshot::channel();
 
-    if controlling {
+    let negotiated = channel_options
+        .as_ref()
+        .and_then(|channel_options| channel_options.negotiated);
+
+    // NOTE(emily): Negotiated channels are made by both sides and never announced, so there is
+    // nothing to wait for.
+    if controlling || negotiated.is_some() {
         let mut init = DataChannelInit::default();
+        if let Some(stream) = negotiated {
+            init = init.negotiated().manual_stream().stream(stream);
+        }
+
         if let Some(channel_options) = channel_options {
             let mut reliability = Reliability::default();
             match channel_options.max_retransmits {
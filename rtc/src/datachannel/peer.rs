@@ -5,12 +5,13 @@ use std::{
 
 use tokio::sync::{mpsc, oneshot, Mutex};
 
-use async_datachannel::{PeerConnection, RtcConfig};
+use async_datachannel::PeerConnection;
 use datachannel::{DataChannelHandler, RtcPeerConnection};
 use eyre::Result;
 
 use crate::{
-    RtcPeerState, ARBITRARY_CHANNEL_LIMIT,
+    CandidatePairStats, IceTransportPolicy, RtcConfig, RtcPeerState, RtcStats,
+    ARBITRARY_CHANNEL_LIMIT,
     {ChannelControl, ChannelEvent, ChannelOptions, RtcPeerControl, RtcPeerEvent},
 };
 
@@ -315,10 +316,31 @@ impl crate::PeerConnection for DatachannelPeerConnection {
     async fn offer(&self, controlling: bool) -> Result<()> {
         Ok(())
     }
+
+    async fn stats(&self) -> Result<RtcStats> {
+        let inner = self.inner.lock().await;
+
+        // NOTE(emily): libdatachannel only tells us which addresses the connection is going
+        // between, nothing about how it is doing.
+        let selected_candidate_pair = match (inner.local_address(), inner.remote_address()) {
+            (Some(local), Some(remote)) => Some(CandidatePairStats {
+                local,
+                remote,
+                ..Default::default()
+            }),
+            _ => None,
+        };
+
+        Ok(RtcStats {
+            selected_candidate_pair,
+            ..Default::default()
+        })
+    }
 }
 
 pub(crate) async fn rtc_peer(
     controlling: bool,
+    rtc_config: RtcConfig,
 ) -> Result<(
     Arc<dyn crate::PeerConnection>,
     mpsc::Sender<RtcPeerControl>,
@@ -330,8 +352,40 @@ pub(crate) async fn rtc_peer(
     telemetry::client::watch_channel(&control_tx, "dc-peer-control").await;
     telemetry::client::watch_channel(&event_tx, "dc-peer-event").await;
 
-    let ice_servers = vec!["stun:stun.l.google.com:19302"];
-    let config = RtcConfig::new(&ice_servers);
+    // NOTE(emily): libdatachannel wants credentials inline, as in `turn:username:credential@host`.
+    let ice_servers: Vec<String> = rtc_config
+        .ice_servers
+        .into_iter()
+        .flat_map(|ice_server| {
+            let auth = match (&ice_server.username, &ice_server.credential) {
+                (Some(username), Some(credential)) => Some(format!("{username}:{credential}@")),
+                _ => None,
+            };
+            ice_server
+                .urls
+                .into_iter()
+                .map(move |url| match (&auth, url.split_once(':')) {
+                    (Some(auth), Some((scheme, rest))) => format!("{scheme}:{auth}{rest}"),
+                    _ => url,
+                })
+        })
+        .collect();
+    let mut config = async_datachannel::RtcConfig::new(&ice_servers);
+    config.ice_transport_policy = match rtc_config.ice_transport_policy {
+        IceTransportPolicy::All => datachannel::TransportPolicy::All,
+        IceTransportPolicy::Relay => datachannel::TransportPolicy::Relay,
+    };
+    if let Some(udp_ports) = &rtc_config.udp_ports {
+        config.port_range_begin = *udp_ports.start();
+        config.port_range_end = *udp_ports.end();
+    }
+    // NOTE(emily): libdatachannel has no way to filter candidates or map addresses.
+    if !rtc_config.interfaces.is_empty()
+        || !rtc_config.ips.is_empty()
+        || !rtc_config.nat_1to1_ips.is_empty()
+    {
+        tracing::warn!("interface, ip and nat 1:1 settings are ignored by libdatachannel");
+    }
 
     let storage = DatachannelStorage::default();
 
@@ -376,6 +430,9 @@ pub(crate) async fn rtc_peer(
                             .await
                             .set_remote_description(&serde_json::from_str(&answer).unwrap())
                             .unwrap(),
+                        RtcPeerControl::RestartIce => {
+                            tracing::warn!("libdatachannel cannot restart ice");
+                        }
                     }
                 }
             }
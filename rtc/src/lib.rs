@@ -3,7 +3,10 @@ mod datachannel;
 
 mod webrtc;
 
-use std::{collections::HashMap, fmt::Display, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap, fmt::Display, net::IpAddr, ops::RangeInclusive, str::FromStr, sync::Arc,
+    time::Duration,
+};
 
 use tokio::sync::{mpsc, Mutex};
 
@@ -26,12 +29,18 @@ pub enum RtcPeerEvent {
     StateChange(RtcPeerState),
     Offer(String),
     Answer(String),
+    /// An ICE restart offer has been made, it goes to the other side like any other offer.
+    IceRestarting,
+    /// Connected again after being disconnected.
+    Recovered,
 }
 
 pub enum RtcPeerControl {
     IceCandidate(String),
     Offer(String),
     Answer(String),
+    /// Make an offer with new ICE credentials so that candidates are gathered and checked again.
+    RestartIce,
     Failed,
 }
 
@@ -59,11 +68,103 @@ pub trait PeerConnection: Send + Sync {
     ) -> Result<(mpsc::Sender<ChannelControl>, mpsc::Receiver<ChannelEvent>)>;
 
     async fn offer(&self, controlling: bool) -> Result<()>;
+
+    async fn stats(&self) -> Result<RtcStats>;
+}
+
+/// How a connection is doing right now. Anything that the backend cannot tell us is left empty.
+#[derive(Debug, Clone, Default)]
+pub struct RtcStats {
+    /// Most recent round trip time on the selected candidate pair.
+    pub round_trip_time: Option<Duration>,
+    pub selected_candidate_pair: Option<CandidatePairStats>,
+    pub channels: Vec<ChannelStats>,
+}
+
+/// The pair of candidates that the connection is going through.
+#[derive(Debug, Clone, Default)]
+pub struct CandidatePairStats {
+    /// Our end, as `address:port` followed by the candidate type.
+    pub local: String,
+    pub remote: String,
+    pub bytes_sent: Option<u64>,
+    pub bytes_received: Option<u64>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ChannelStats {
+    pub label: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct ChannelOptions {
     pub ordered: Option<bool>,
     pub max_retransmits: Option<u16>,
+    /// Agreed SCTP stream id. Both sides make the channel themselves with this id instead of the
+    /// controlling side opening it and the other side waiting for it to turn up by label.
+    pub negotiated: Option<u16>,
+}
+
+/// A STUN or TURN server to gather candidates from.
+#[derive(Debug, Clone, Default)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    pub username: Option<String>,
+    pub credential: Option<String>,
+}
+
+impl IceServer {
+    /// Public STUN servers, for when nobody has given us anything better.
+    pub fn public_stun() -> Vec<Self> {
+        vec![Self {
+            urls: vec![
+                "stun:stun.l.google.com:19302".to_owned(),
+                "stun:stun.cloudflare.com:3478".to_owned(),
+            ],
+            ..Default::default()
+        }]
+    }
+}
+
+/// Which candidates a connection is allowed to use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IceTransportPolicy {
+    #[default]
+    All,
+    /// Only go through TURN servers, never straight to the other peer.
+    Relay,
+}
+
+/// How a peer connection finds its way to the other side.
+#[derive(Debug, Clone)]
+pub struct RtcConfig {
+    pub ice_servers: Vec<IceServer>,
+    pub ice_transport_policy: IceTransportPolicy,
+    /// Only gather candidates on these network interfaces, if empty use all of them.
+    pub interfaces: Vec<String>,
+    /// Only gather candidates on these local addresses, if empty use all of them.
+    pub ips: Vec<IpAddr>,
+    /// Local UDP ports to gather candidates on, if unset any port will do.
+    pub udp_ports: Option<RangeInclusive<u16>>,
+    /// Public addresses to hand out in place of our own, for when we are behind a 1:1 NAT.
+    pub nat_1to1_ips: Vec<IpAddr>,
+}
+
+impl Default for RtcConfig {
+    fn default() -> Self {
+        Self {
+            ice_servers: IceServer::public_stun(),
+            ice_transport_policy: IceTransportPolicy::All,
+            interfaces: vec![],
+            ips: vec![],
+            udp_ports: None,
+            nat_1to1_ips: vec![],
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -77,15 +178,16 @@ impl Api {
     pub async fn peer(
         &self,
         controlling: bool,
+        config: RtcConfig,
     ) -> Result<(
         Arc<dyn PeerConnection>,
         mpsc::Sender<RtcPeerControl>,
         mpsc::Receiver<RtcPeerEvent>,
     )> {
         match self {
-            Self::WebrtcRs => self::webrtc::peer::rtc_peer(controlling).await,
+            Self::WebrtcRs => self::webrtc::peer::rtc_peer(controlling, config).await,
             #[cfg(feature = "datachannel")]
-            Self::DataChannel => self::datachannel::peer::rtc_peer(controlling).await,
+            Self::DataChannel => self::datachannel::peer::rtc_peer(controlling, config).await,
         }
     }
 }
@@ -7,16 +7,32 @@ use ::webrtc::{data_channel::RTCDataChannel, peer_connection::RTCPeerConnection}
 use tokio::sync::mpsc;
 use webrtc::{
     data_channel::data_channel_init::RTCDataChannelInit,
-    peer_connection::signaling_state::RTCSignalingState,
+    ice_transport::ice_candidate_pair::RTCIceCandidatePair,
+    peer_connection::signaling_state::RTCSignalingState, stats::StatsReportType,
 };
 
 use self::channel::ChannelStorage;
 
-use super::{ChannelControl, ChannelEvent, ChannelOptions, DataChannel, PeerConnection};
+use super::{
+    CandidatePairStats, ChannelControl, ChannelEvent, ChannelOptions, ChannelStats, DataChannel,
+    PeerConnection, RtcStats,
+};
 use eyre::Result;
 
 impl DataChannel for RTCDataChannel {}
 
+/// The stats ids of the local and remote candidates in `pair`.
+fn candidate_ids(pair: &RTCIceCandidatePair) -> Option<(String, String)> {
+    // NOTE(emily): RTCIceCandidatePair keeps its candidates to itself, but its Debug output has
+    // the pair's stats id followed by each candidate's, which are what the stats reports use.
+    let debug = format!("{pair:?}");
+    let mut ids = debug
+        .split("stats_id: \"")
+        .skip(2)
+        .filter_map(|rest| rest.split_once('"').map(|(id, _)| id.to_owned()));
+    Some((ids.next()?, ids.next()?))
+}
+
 use derive_more::{Deref, DerefMut};
 
 #[derive(Deref, DerefMut)]
@@ -54,6 +70,7 @@ impl PeerConnection for WebrtcRsPeerConnection {
             channel_options.map(|options| RTCDataChannelInit {
                 ordered: options.ordered,
                 max_retransmits: options.max_retransmits,
+                negotiated: options.negotiated,
                 ..Default::default()
             }),
         )
@@ -70,6 +87,62 @@ impl PeerConnection for WebrtcRsPeerConnection {
 
         Ok(())
     }
+
+    async fn stats(&self) -> Result<RtcStats> {
+        let reports = self.inner.get_stats().await.reports;
+        let selected = self
+            .inner
+            .sctp()
+            .transport()
+            .ice_transport()
+            .get_selected_candidate_pair()
+            .await
+            .as_ref()
+            .and_then(candidate_ids);
+
+        let mut stats = RtcStats::default();
+
+        let candidate = |id: &str| match reports.get(id) {
+            Some(StatsReportType::LocalCandidate(candidate))
+            | Some(StatsReportType::RemoteCandidate(candidate)) => format!(
+                "{}:{} {}",
+                candidate.ip, candidate.port, candidate.candidate_type
+            ),
+            _ => id.to_owned(),
+        };
+
+        for report in reports.values() {
+            match report {
+                StatsReportType::CandidatePair(pair)
+                    if selected.as_ref().is_some_and(|(local, remote)| {
+                        pair.local_candidate_id == *local && pair.remote_candidate_id == *remote
+                    }) =>
+                {
+                    stats.round_trip_time = Some(std::time::Duration::from_secs_f64(
+                        pair.current_round_trip_time,
+                    ));
+                    stats.selected_candidate_pair = Some(CandidatePairStats {
+                        local: candidate(&pair.local_candidate_id),
+                        remote: candidate(&pair.remote_candidate_id),
+                        bytes_sent: Some(pair.bytes_sent),
+                        bytes_received: Some(pair.bytes_received),
+                    });
+                }
+                StatsReportType::DataChannel(channel) => stats.channels.push(ChannelStats {
+                    label: channel.label.clone(),
+                    bytes_sent: channel.bytes_sent as u64,
+                    bytes_received: channel.bytes_received as u64,
+                    messages_sent: channel.messages_sent as u64,
+                    messages_received: channel.messages_received as u64,
+                }),
+                _ => {}
+            }
+        }
+
+        stats.channels.sort_by(|a, b| a.label.cmp(&b.label));
+
+        Ok(stats)
+    }
 }
 
 impl Drop for WebrtcRsPeerConnection {
@@ -77,3 +150,200 @@ impl Drop for WebrtcRsPeerConnection {
         tracing::info!("WebrtcRsPeerConnection::drop");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        Api, ChannelControl, ChannelEvent, ChannelOptions, PeerConnection, RtcConfig,
+        RtcPeerControl, RtcPeerEvent,
+    };
+
+    const LABELS: [&str; 3] = ["logic", "audio", "video"];
+
+    type Peer = (
+        Arc<dyn PeerConnection>,
+        mpsc::Sender<RtcPeerControl>,
+        mpsc::Receiver<RtcPeerEvent>,
+    );
+
+    /// Pass offers, answers and candidates from one side to the other, like signalling would.
+    fn forward(mut events: mpsc::Receiver<RtcPeerEvent>, control: mpsc::Sender<RtcPeerControl>) {
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let message = match event {
+                    RtcPeerEvent::Offer(offer) => RtcPeerControl::Offer(offer),
+                    RtcPeerEvent::Answer(answer) => RtcPeerControl::Answer(answer),
+                    RtcPeerEvent::IceCandidate(candidate) => {
+                        RtcPeerControl::IceCandidate(candidate)
+                    }
+                    _ => continue,
+                };
+                if control.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Two peers that signal each other, with no STUN servers so that they stay local.
+    async fn peers() -> (Peer, Peer) {
+        let config = RtcConfig {
+            ice_servers: vec![],
+            ..Default::default()
+        };
+        let a = Api::WebrtcRs.peer(true, config.clone()).await.unwrap();
+        let b = Api::WebrtcRs.peer(false, config).await.unwrap();
+        (a, b)
+    }
+
+    fn connect(a: Peer, b: Peer) -> (Arc<dyn PeerConnection>, Arc<dyn PeerConnection>) {
+        let (a, a_control, a_events) = a;
+        let (b, b_control, b_events) = b;
+        forward(a_events, b_control);
+        forward(b_events, a_control);
+        (a, b)
+    }
+
+    async fn open_all(
+        peer: &dyn PeerConnection,
+        controlling: bool,
+    ) -> Vec<(mpsc::Sender<ChannelControl>, mpsc::Receiver<ChannelEvent>)> {
+        let (logic, audio, video) = tokio::join!(
+            peer.channel(LABELS[0], controlling, None),
+            peer.channel(LABELS[1], controlling, None),
+            peer.channel(LABELS[2], controlling, None),
+        );
+        vec![logic.unwrap(), audio.unwrap(), video.unwrap()]
+    }
+
+    async fn next_event(rx: &mut mpsc::Receiver<ChannelEvent>) -> ChannelEvent {
+        tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("timed out waiting for a channel event")
+            .expect("channel went away")
+    }
+
+    async fn expect_message(rx: &mut mpsc::Receiver<ChannelEvent>, label: &str) {
+        assert!(matches!(next_event(rx).await, ChannelEvent::Open));
+        match next_event(rx).await {
+            ChannelEvent::Message(data) => assert_eq!(data, label.as_bytes()),
+            _ => panic!("expected a message on {label}"),
+        }
+    }
+
+    async fn send_labels(
+        channels: &mut [(mpsc::Sender<ChannelControl>, mpsc::Receiver<ChannelEvent>)],
+    ) {
+        for ((control, events), label) in channels.iter_mut().zip(LABELS) {
+            assert!(matches!(next_event(events).await, ChannelEvent::Open));
+            control
+                .send(ChannelControl::Send(label.as_bytes().to_vec()))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_channels_open_concurrently() {
+        let (a, b) = peers().await;
+        let (a, b) = connect(a, b);
+
+        let (mut a_channels, mut b_channels) =
+            tokio::join!(open_all(&*a, true), open_all(&*b, false));
+        a.offer(true).await.unwrap();
+
+        send_labels(&mut a_channels).await;
+        for ((_, events), label) in b_channels.iter_mut().zip(LABELS) {
+            expect_message(events, label).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_channels_opened_before_registration() {
+        let (a, b) = peers().await;
+        let (a, b) = connect(a, b);
+
+        let mut a_channels = open_all(&*a, true).await;
+        a.offer(true).await.unwrap();
+        send_labels(&mut a_channels).await;
+
+        // NOTE(emily): Everything has arrived on this side before anybody asks for it.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let mut b_channels = open_all(&*b, false).await;
+        for ((_, events), label) in b_channels.iter_mut().zip(LABELS) {
+            expect_message(events, label).await;
+        }
+
+        assert!(b.channel(LABELS[0], false, None).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_negotiated_channels() {
+        let (a, b) = peers().await;
+        let (a, b) = connect(a, b);
+
+        async fn open_negotiated(
+            peer: &dyn PeerConnection,
+            controlling: bool,
+        ) -> Vec<(mpsc::Sender<ChannelControl>, mpsc::Receiver<ChannelEvent>)> {
+            let mut channels = vec![];
+            for (id, label) in (0..).zip(LABELS) {
+                let options = ChannelOptions {
+                    negotiated: Some(id),
+                    ..Default::default()
+                };
+                channels.push(
+                    peer.channel(label, controlling, Some(options))
+                        .await
+                        .unwrap(),
+                );
+            }
+            channels
+        }
+
+        let (mut a_channels, mut b_channels) =
+            tokio::join!(open_negotiated(&*a, true), open_negotiated(&*b, false));
+        a.offer(true).await.unwrap();
+
+        send_labels(&mut a_channels).await;
+        for ((_, events), label) in b_channels.iter_mut().zip(LABELS) {
+            expect_message(events, label).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stats_follow_selected_pair() {
+        let (a, b) = peers().await;
+        let (a, b) = connect(a, b);
+
+        let (mut a_channels, mut b_channels) =
+            tokio::join!(open_all(&*a, true), open_all(&*b, false));
+        a.offer(true).await.unwrap();
+
+        send_labels(&mut a_channels).await;
+        for ((_, events), label) in b_channels.iter_mut().zip(LABELS) {
+            expect_message(events, label).await;
+        }
+
+        for peer in [&a, &b] {
+            let stats = peer.stats().await.unwrap();
+            let pair = stats.selected_candidate_pair.expect("no selected pair");
+            // NOTE(emily): Both ends are candidates that the reports know about, not bare ids.
+            assert!(pair.local.contains(':') && pair.remote.contains(':'));
+            assert!(stats.round_trip_time.is_some());
+            assert_eq!(
+                stats
+                    .channels
+                    .iter()
+                    .map(|channel| channel.label.as_str())
+                    .collect::<Vec<_>>(),
+                ["audio", "logic", "video"]
+            );
+        }
+    }
+}
@@ -12,7 +12,7 @@ use webrtc::{
     peer_connection::RTCPeerConnection,
 };
 
-use eyre::Result;
+use eyre::{eyre, Result};
 
 use crate::{
     ARBITRARY_RTC_CHANNEL_LIMIT, {ChannelControl, ChannelEvent},
@@ -21,21 +21,62 @@ use crate::{
 const BUFFERED_AMOUNT_LOW_THRESHOLD: usize = 500_000;
 const MAX_BUFFERED_AMOUNT: usize = 1_000_000;
 
+/// Both ends of a channel's controls and events, made by whichever comes first out of us asking
+/// for the channel and the other side opening it.
+pub(crate) struct Registration {
+    control_rx: Arc<Mutex<Option<mpsc::Receiver<ChannelControl>>>>,
+    control_tx: mpsc::Sender<ChannelControl>,
+    event_tx: mpsc::Sender<ChannelEvent>,
+    /// Taken by whoever asks for the channel, until then events queue up in here.
+    event_rx: Option<mpsc::Receiver<ChannelEvent>>,
+}
+
+impl Registration {
+    fn new() -> Self {
+        let (control_tx, control_rx) = mpsc::channel(ARBITRARY_RTC_CHANNEL_LIMIT);
+        let (event_tx, event_rx) = mpsc::channel(ARBITRARY_RTC_CHANNEL_LIMIT);
+
+        Self {
+            control_rx: Arc::new(Mutex::new(Some(control_rx))),
+            control_tx,
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+}
+
 #[derive(derive_more::Deref, derive_more::DerefMut, Clone, Default)]
-pub(crate) struct ChannelStorage(
-    Arc<
-        Mutex<
-            HashMap<
-                String,
-                (
-                    Arc<Mutex<Option<mpsc::Receiver<ChannelControl>>>>,
-                    mpsc::Sender<ChannelEvent>,
-                    mpsc::Sender<ChannelControl>,
-                ),
-            >,
-        >,
-    >,
-);
+pub(crate) struct ChannelStorage(Arc<Mutex<HashMap<String, Registration>>>);
+
+impl ChannelStorage {
+    async fn register(
+        &self,
+        label: &str,
+    ) -> (
+        Arc<Mutex<Option<mpsc::Receiver<ChannelControl>>>>,
+        mpsc::Sender<ChannelEvent>,
+        mpsc::Sender<ChannelControl>,
+    ) {
+        let mut storage = self.lock().await;
+        let registration = storage
+            .entry(label.to_owned())
+            .or_insert_with(Registration::new);
+
+        (
+            registration.control_rx.clone(),
+            registration.event_tx.clone(),
+            registration.control_tx.clone(),
+        )
+    }
+
+    /// The events for a channel, only the first caller gets them.
+    async fn take_events(&self, label: &str) -> Option<mpsc::Receiver<ChannelEvent>> {
+        self.lock()
+            .await
+            .get_mut(label)
+            .and_then(|registration| registration.event_rx.take())
+    }
+}
 
 #[tracing::instrument(skip(channel, event_tx, control_rx, control_tx))]
 async fn on_datachannel(
@@ -64,6 +105,10 @@ async fn on_datachannel(
         })
     });
 
+    // NOTE(emily): webrtc-rs runs on_open on its own task, so for channels that the other side
+    // opened a message can beat it. Whichever comes first says that the channel is open.
+    let opened: Arc<tokio::sync::OnceCell<()>> = Default::default();
+
     // Use mpsc channel to send and receive a signal when more data can be sent
     let (more_can_be_sent, mut maybe_more_can_be_sent) = tokio::sync::mpsc::channel(1);
 
@@ -75,12 +120,17 @@ async fn on_datachannel(
         let our_label = our_label.clone();
         let channel = Arc::downgrade(&channel);
         let event_tx = event_tx.clone();
+        let opened = opened.clone();
         let _control_rx_holder = control_rx.clone();
         Box::new(move || {
             // let channel = channel.clone();
             Box::pin(async move {
                 tracing::debug!("channel {our_label} open");
-                event_tx.send(ChannelEvent::Open).await.unwrap();
+                opened
+                    .get_or_init(|| async {
+                        event_tx.send(ChannelEvent::Open).await.unwrap();
+                    })
+                    .await;
 
                 // NOTE(emily): Only start handling controls once the data channel is open.
                 // This gives us natural back-pressure whilst we are waiting for the channel to open
@@ -168,11 +218,18 @@ async fn on_datachannel(
 
         Box::new(move |msg: DataChannelMessage| {
             let event_tx = event_tx.clone();
+            let opened = opened.clone();
             let our_label = our_label.clone();
 
             recv_counter.update(msg.data.len());
 
             Box::pin(async move {
+                opened
+                    .get_or_init(|| async {
+                        let _ = event_tx.send(ChannelEvent::Open).await;
+                    })
+                    .await;
+
                 if let Err(_) = event_tx
                     .send(ChannelEvent::Message(msg.data.to_vec()))
                     .await
@@ -198,6 +255,31 @@ async fn on_datachannel(
     Ok(())
 }
 
+/// Route every channel that the other side opens to whoever asks for it by label. There is only
+/// one `on_data_channel` per peer connection so this is installed once, up front.
+pub(crate) fn dispatch(storage: ChannelStorage, peer_connection: &RTCPeerConnection) {
+    peer_connection.on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
+        let channel_label = d.label().to_owned();
+        let id = d.id();
+
+        tracing::debug!("New DataChannel {channel_label} {id}");
+
+        Box::pin({
+            let storage = storage.clone();
+            async move {
+                // NOTE(emily): If nobody has asked for this channel yet then its events wait
+                // in the registration until they do.
+                let (control_rx, event_tx, control_tx) = storage.register(&channel_label).await;
+                if let Err(err) =
+                    on_datachannel(d, channel_label, event_tx, control_rx, control_tx).await
+                {
+                    tracing::error!("unable to set up incoming channel {err}");
+                }
+            }
+        })
+    }));
+}
+
 #[tracing::instrument(skip(storage, peer_connection))]
 pub(crate) async fn channel(
     storage: ChannelStorage,
@@ -207,46 +289,20 @@ pub(crate) async fn channel(
     channel_options: Option<RTCDataChannelInit>,
 ) -> Result<(mpsc::Sender<ChannelControl>, mpsc::Receiver<ChannelEvent>)> {
     let our_label = our_label.to_owned();
-    let (control_tx, control_rx) = mpsc::channel(ARBITRARY_RTC_CHANNEL_LIMIT);
-    let (event_tx, event_rx) = mpsc::channel(ARBITRARY_RTC_CHANNEL_LIMIT);
-
-    let control_rx = Arc::new(Mutex::new(Some(control_rx)));
 
-    {
-        storage.lock().await.insert(
-            our_label.clone(),
-            (control_rx.clone(), event_tx.clone(), control_tx.clone()),
-        );
-    }
+    let (control_rx, event_tx, control_tx) = storage.register(&our_label).await;
+    let event_rx = storage
+        .take_events(&our_label)
+        .await
+        .ok_or_else(|| eyre!("channel {our_label} already exists"))?;
 
-    // Register data channel creation handling
-    // TODO(emily): There is only one on_data_channel per peer you silly.
-    peer_connection.on_data_channel({
-        let storage = storage.clone();
-        Box::new(move |d: Arc<RTCDataChannel>| {
-            let channel_label = d.label().to_owned();
-            let id = d.id();
-
-            tracing::debug!("New DataChannel {} {id}", d.label());
-
-            Box::pin({
-                let storage = storage.clone();
-                async move {
-                    if let Some(storage) = storage.lock().await.get(&channel_label) {
-                        let our_label = channel_label;
-                        let control_rx = storage.0.clone();
-                        let event_tx = storage.1.clone();
-                        let control_tx = storage.2.clone();
-                        on_datachannel(d, our_label, event_tx, control_rx, control_tx)
-                            .await
-                            .unwrap();
-                    }
-                }
-            })
-        })
-    });
+    // NOTE(emily): Negotiated channels are made by both sides and never announced, so they do not
+    // come through the dispatcher.
+    let negotiated = channel_options
+        .as_ref()
+        .is_some_and(|channel_options| channel_options.negotiated.is_some());
 
-    if controlling {
+    if controlling || negotiated {
         // Create a datachannel with label
         let data_channel = peer_connection
             .create_data_channel(&our_label, channel_options)
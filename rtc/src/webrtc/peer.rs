@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
 use tokio::sync::mpsc;
 
@@ -8,22 +11,30 @@ use webrtc::{
         interceptor_registry::register_default_interceptors, media_engine::MediaEngine,
         setting_engine, APIBuilder,
     },
-    ice_transport::{ice_candidate::RTCIceCandidateInit, ice_server::RTCIceServer},
+    ice::udp_network::{EphemeralUDP, UDPNetwork},
+    ice_transport::{
+        ice_candidate::RTCIceCandidateInit, ice_candidate_type::RTCIceCandidateType,
+        ice_server::RTCIceServer,
+    },
     interceptor::registry::Registry,
     peer_connection::{
-        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
+        configuration::RTCConfiguration, offer_answer_options::RTCOfferOptions,
+        peer_connection_state::RTCPeerConnectionState,
+        policy::ice_transport_policy::RTCIceTransportPolicy,
         sdp::session_description::RTCSessionDescription, signaling_state::RTCSignalingState,
     },
 };
 
 use crate::{
-    ARBITRARY_RTC_CHANNEL_LIMIT, {RtcPeerControl, RtcPeerEvent, RtcPeerState},
+    IceTransportPolicy, RtcConfig, ARBITRARY_RTC_CHANNEL_LIMIT,
+    {RtcPeerControl, RtcPeerEvent, RtcPeerState},
 };
 
 use super::{channel::ChannelStorage, WebrtcRsPeerConnection};
 
 pub(crate) async fn rtc_peer(
     controlling: bool,
+    rtc_config: RtcConfig,
 ) -> Result<(
     Arc<dyn crate::PeerConnection>,
     mpsc::Sender<RtcPeerControl>,
@@ -47,7 +58,35 @@ pub(crate) async fn rtc_peer(
     // for each PeerConnection.
     let mut registry = Registry::new();
 
-    let setting_engine = setting_engine::SettingEngine::default();
+    let mut setting_engine = setting_engine::SettingEngine::default();
+
+    if !rtc_config.interfaces.is_empty() {
+        let interfaces = rtc_config.interfaces.clone();
+        setting_engine.set_interface_filter(Box::new(move |interface: &str| {
+            interfaces.iter().any(|allowed| allowed == interface)
+        }));
+    }
+    if !rtc_config.ips.is_empty() {
+        let ips = rtc_config.ips.clone();
+        setting_engine.set_ip_filter(Box::new(move |ip| ips.contains(&ip)));
+    }
+    if let Some(udp_ports) = &rtc_config.udp_ports {
+        setting_engine.set_udp_network(UDPNetwork::Ephemeral(EphemeralUDP::new(
+            *udp_ports.start(),
+            *udp_ports.end(),
+        )?));
+    }
+    if !rtc_config.nat_1to1_ips.is_empty() {
+        // NOTE(emily): Host candidates so that the private addresses never leave this machine.
+        setting_engine.set_nat_1to1_ips(
+            rtc_config
+                .nat_1to1_ips
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect(),
+            RTCIceCandidateType::Host,
+        );
+    }
 
     // Use the default set of Interceptors
     registry = register_default_interceptors(registry, &mut m)?;
@@ -61,13 +100,20 @@ pub(crate) async fn rtc_peer(
 
     // Prepare the configuration
     let config = RTCConfiguration {
-        ice_servers: vec![RTCIceServer {
-            urls: vec![
-                "stun:stun.l.google.com:19302".to_owned(),
-                "stun:stun.cloudflare.com:3478".to_owned(),
-            ],
-            ..Default::default()
-        }],
+        ice_servers: rtc_config
+            .ice_servers
+            .into_iter()
+            .map(|ice_server| RTCIceServer {
+                urls: ice_server.urls,
+                username: ice_server.username.unwrap_or_default(),
+                credential: ice_server.credential.unwrap_or_default(),
+                ..Default::default()
+            })
+            .collect(),
+        ice_transport_policy: match rtc_config.ice_transport_policy {
+            IceTransportPolicy::All => RTCIceTransportPolicy::All,
+            IceTransportPolicy::Relay => RTCIceTransportPolicy::Relay,
+        },
         ..Default::default()
     };
 
@@ -76,6 +122,12 @@ pub(crate) async fn rtc_peer(
         api.new_peer_connection(config).await?,
     ));
 
+    let storage = ChannelStorage::default();
+    super::channel::dispatch(storage.clone(), &peer_connection);
+
+    // NOTE(emily): Set whilst disconnected, so that coming back can be reported as recovering.
+    let interrupted = Arc::new(AtomicBool::new(false));
+
     // Set the handler for Peer connection state
     // This will notify you when the peer has connected/disconnected
     peer_connection.on_peer_connection_state_change({
@@ -84,6 +136,7 @@ pub(crate) async fn rtc_peer(
         Box::new(move |s: RTCPeerConnectionState| {
             let event_tx = event_tx.clone();
             let control_tx = control_tx.clone();
+            let interrupted = interrupted.clone();
 
             tracing::debug!("peer Connection State has changed: {s}");
 
@@ -108,6 +161,18 @@ pub(crate) async fn rtc_peer(
                         RTCPeerConnectionState::Closed => RtcPeerState::Closed,
                     }))
                     .await;
+
+                match s {
+                    RTCPeerConnectionState::Disconnected => {
+                        interrupted.store(true, Ordering::Relaxed);
+                    }
+                    RTCPeerConnectionState::Connected
+                        if interrupted.swap(false, Ordering::Relaxed) =>
+                    {
+                        let _ = event_tx.send(RtcPeerEvent::Recovered).await;
+                    }
+                    _ => {}
+                }
             })
         })
     });
@@ -214,6 +279,31 @@ pub(crate) async fn rtc_peer(
                                 .unwrap();
                         }
                     }
+                    RtcPeerControl::RestartIce => {
+                        tracing::info!("restarting ice");
+
+                        // NOTE(emily): The offer itself goes out once the signaling state changes,
+                        // like the first one did.
+                        let restarted = async {
+                            let offer = peer_connection
+                                .create_offer(Some(RTCOfferOptions {
+                                    ice_restart: true,
+                                    ..Default::default()
+                                }))
+                                .await?;
+                            peer_connection.set_local_description(offer).await
+                        }
+                        .await;
+
+                        match restarted {
+                            Ok(()) => {
+                                let _ = event_tx.send(RtcPeerEvent::IceRestarting).await;
+                            }
+                            Err(err) => {
+                                tracing::warn!(%err, "unable to restart ice");
+                            }
+                        }
+                    }
                     RtcPeerControl::Failed => {
                         tracing::debug!("Peer failed, bailing rtc control");
                         break;
@@ -226,8 +316,6 @@ pub(crate) async fn rtc_peer(
         }
     });
 
-    let storage = ChannelStorage::default();
-
     let peer_connection = Arc::new(WebrtcRsPeerConnection {
         inner: peer_connection,
         storage: storage,
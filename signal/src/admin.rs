@@ -0,0 +1,305 @@
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+
+use crate::{ConnectionId, PeerId, PeerMetadata, ServerState};
+
+/// An HTTP listener for keeping an eye on the signal server.
+#[derive(Clone)]
+pub struct AdminOptions {
+    /// e.g. `127.0.0.1:9090`.
+    pub address: String,
+    /// Needed (as `Authorization: Bearer <token>`) for everything except `/healthz`. Kicking
+    /// peers is disabled without it, and the server refuses to start without it unless `address`
+    /// is a loopback address.
+    pub token: Option<String>,
+}
+
+impl std::fmt::Debug for AdminOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdminOptions")
+            .field("address", &self.address)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Messages that the server passes on from one peer to another.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Relayed {
+    Offer,
+    Answer,
+    IceCandidate,
+    ConnectionRequest,
+    ConnectionAccepted,
+    ConnectionRejected,
+}
+
+impl Relayed {
+    const ALL: [Self; 6] = [
+        Self::Offer,
+        Self::Answer,
+        Self::IceCandidate,
+        Self::ConnectionRequest,
+        Self::ConnectionAccepted,
+        Self::ConnectionRejected,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Offer => "offer",
+            Self::Answer => "answer",
+            Self::IceCandidate => "ice_candidate",
+            Self::ConnectionRequest => "connection_request",
+            Self::ConnectionAccepted => "connection_accepted",
+            Self::ConnectionRejected => "connection_rejected",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    relayed: [AtomicU64; Relayed::ALL.len()],
+    pub(crate) connections: AtomicU64,
+    pub(crate) malformed_messages: AtomicU64,
+    pub(crate) rate_limited_messages: AtomicU64,
+    pub(crate) kicked: AtomicU64,
+    pub(crate) refused_relays: AtomicU64,
+    pub(crate) dropped_messages: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn relayed(&self, relayed: Relayed) {
+        self.relayed[relayed as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Serialize)]
+struct AdminPeer {
+    peer_id: PeerId,
+    metadata: PeerMetadata,
+    address: SocketAddr,
+    /// Seconds since the unix epoch.
+    connected_at: u64,
+    /// Lost their connection and have not resumed yet.
+    suspended: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct AdminConnectionRequest {
+    connection_id: ConnectionId,
+    requester: PeerId,
+    requestee: PeerId,
+    /// Seconds since the unix epoch.
+    requested_at: u64,
+}
+
+pub(crate) fn router(state: ServerState) -> Router {
+    Router::new()
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/peers", get(peers))
+        .route("/peers/:peer_id/kick", post(kick))
+        .route("/connection-requests", get(connection_requests))
+        .route("/metrics", get(metrics))
+        .with_state(state)
+}
+
+fn authorized(state: &ServerState, headers: &HeaderMap) -> bool {
+    let Some(admin) = &state.options.admin else {
+        return false;
+    };
+    let Some(token) = &admin.token else {
+        return true;
+    };
+
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| {
+            crate::auth::constant_time_eq(presented.as_bytes(), token.as_bytes())
+        })
+}
+
+async fn peers(State(state): State<ServerState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let suspended: Vec<PeerId> = state
+        .suspended_peers
+        .lock()
+        .await
+        .values()
+        .map(|suspended_peer| suspended_peer.peer_id.clone())
+        .collect();
+
+    let peers: Vec<_> = state
+        .peers
+        .lock()
+        .await
+        .iter()
+        .map(|(peer_id, peer)| AdminPeer {
+            peer_id: peer_id.clone(),
+            metadata: peer.metadata.clone(),
+            address: peer.address,
+            connected_at: unix_time(peer.connected_at),
+            suspended: suspended.contains(peer_id),
+        })
+        .collect();
+
+    Json(peers).into_response()
+}
+
+async fn connection_requests(State(state): State<ServerState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let connection_requests: Vec<_> = state
+        .connection_requests
+        .lock()
+        .await
+        .iter()
+        .map(|(connection_id, request)| AdminConnectionRequest {
+            connection_id: *connection_id,
+            requester: request.requester.clone(),
+            requestee: request.requestee.clone(),
+            requested_at: unix_time(request.requested_at),
+        })
+        .collect();
+
+    Json(connection_requests).into_response()
+}
+
+async fn kick(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    Path(peer_id): Path<String>,
+) -> Response {
+    let has_token = state
+        .options
+        .admin
+        .as_ref()
+        .is_some_and(|admin| admin.token.is_some());
+
+    if !has_token {
+        return (StatusCode::FORBIDDEN, "kicking peers needs an admin token").into_response();
+    }
+    if !authorized(&state, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    if crate::kick_peer(&state, &PeerId::from(peer_id)).await {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+async fn metrics(State(state): State<ServerState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let peers = state.peers.lock().await.len();
+    let suspended_peers = state.suspended_peers.lock().await.len();
+    let connection_requests = state.connection_requests.lock().await.len();
+    let rooms = state.rooms.lock().await.len();
+    let metrics = &state.metrics;
+
+    let mut text = String::new();
+
+    let _ = writeln!(
+        text,
+        "# HELP signal_messages_relayed_total Messages passed on between peers."
+    );
+    let _ = writeln!(text, "# TYPE signal_messages_relayed_total counter");
+    for relayed in Relayed::ALL {
+        let _ = writeln!(
+            text,
+            "signal_messages_relayed_total{{type=\"{}\"}} {}",
+            relayed.label(),
+            metrics.relayed[relayed as usize].load(Ordering::Relaxed)
+        );
+    }
+
+    for (name, help, value) in [
+        (
+            "signal_connections_total",
+            "Websocket connections accepted.",
+            metrics.connections.load(Ordering::Relaxed),
+        ),
+        (
+            "signal_malformed_messages_total",
+            "Malformed, unsupported or oversized messages received.",
+            metrics.malformed_messages.load(Ordering::Relaxed),
+        ),
+        (
+            "signal_rate_limited_messages_total",
+            "Messages refused for going over a rate limit.",
+            metrics.rate_limited_messages.load(Ordering::Relaxed),
+        ),
+        (
+            "signal_kicked_total",
+            "Peers kicked by an admin.",
+            metrics.kicked.load(Ordering::Relaxed),
+        ),
+        (
+            "signal_refused_relays_total",
+            "Offers, answers and candidates refused for going to a peer that never accepted.",
+            metrics.refused_relays.load(Ordering::Relaxed),
+        ),
+        (
+            "signal_dropped_messages_total",
+            "Messages dropped because the peer that they were for had a full channel.",
+            metrics.dropped_messages.load(Ordering::Relaxed),
+        ),
+    ] {
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} counter");
+        let _ = writeln!(text, "{name} {value}");
+    }
+
+    for (name, help, value) in [
+        (
+            "signal_peers",
+            "Peers with an id, including suspended ones.",
+            peers,
+        ),
+        (
+            "signal_suspended_peers",
+            "Peers waiting to resume.",
+            suspended_peers,
+        ),
+        (
+            "signal_pending_connection_requests",
+            "Connection requests waiting for a response.",
+            connection_requests,
+        ),
+        ("signal_rooms", "Rooms that are open.", rooms),
+    ] {
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} gauge");
+        let _ = writeln!(text, "{name} {value}");
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response()
+}
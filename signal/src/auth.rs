@@ -0,0 +1,178 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use eyre::{eyre, Result};
+
+pub(crate) const CHALLENGE_LEN: usize = 32;
+
+/// What a client presents to the signal server to authenticate itself.
+#[derive(Clone, Default)]
+pub enum Credentials {
+    /// For servers that do not require authentication.
+    #[default]
+    None,
+    /// A token shared with the server.
+    Token(String),
+    /// A key whose public half the server knows about.
+    Key(SigningKey),
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            Self::Token(_) => write!(f, "Token(..)"),
+            Self::Key(key) => f
+                .debug_tuple("Key")
+                .field(&hex::encode(key.verifying_key().as_bytes()))
+                .finish(),
+        }
+    }
+}
+
+impl Credentials {
+    pub(crate) fn respond(&self, challenge: &[u8]) -> AuthResponse {
+        match self {
+            Self::None => AuthResponse::Anonymous,
+            Self::Token(token) => AuthResponse::Token(token.clone()),
+            Self::Key(key) => AuthResponse::Signature {
+                public_key: key.verifying_key().to_bytes().to_vec(),
+                signature: key.sign(challenge).to_bytes().to_vec(),
+            },
+        }
+    }
+}
+
+/// Response to the challenge that the server sends when a peer first connects.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum AuthResponse {
+    Anonymous,
+    Token(String),
+    Signature {
+        public_key: Vec<u8>,
+        signature: Vec<u8>,
+    },
+}
+
+impl std::fmt::Debug for AuthResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Anonymous => write!(f, "Anonymous"),
+            Self::Token(_) => write!(f, "Token(..)"),
+            Self::Signature { public_key, .. } => f
+                .debug_struct("Signature")
+                .field("public_key", &hex::encode(public_key))
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Who a peer proved themselves to be when they authenticated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Identity {
+    Anonymous,
+    Token,
+    Key(VerifyingKey),
+}
+
+impl std::fmt::Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Anonymous => write!(f, "anonymous"),
+            Self::Token => write!(f, "token"),
+            Self::Key(key) => write!(f, "key {}", hex::encode(key.as_bytes())),
+        }
+    }
+}
+
+/// How the signal server authenticates peers. If neither a token nor any keys are set then
+/// anyone can connect.
+#[derive(Debug, Default, Clone)]
+pub struct AuthOptions {
+    pub token: Option<String>,
+    pub authorized_keys: Vec<VerifyingKey>,
+}
+
+impl AuthOptions {
+    fn is_open(&self) -> bool {
+        self.token.is_none() && self.authorized_keys.is_empty()
+    }
+
+    pub(crate) fn authenticate(
+        &self,
+        challenge: &[u8],
+        response: &AuthResponse,
+    ) -> Option<Identity> {
+        match response {
+            AuthResponse::Anonymous if self.is_open() => Some(Identity::Anonymous),
+            AuthResponse::Anonymous => None,
+            AuthResponse::Token(token) => match &self.token {
+                Some(expected) if constant_time_eq(expected.as_bytes(), token.as_bytes()) => {
+                    Some(Identity::Token)
+                }
+                Some(_) => None,
+                None if self.is_open() => Some(Identity::Anonymous),
+                None => None,
+            },
+            AuthResponse::Signature {
+                public_key,
+                signature,
+            } => {
+                let public_key = VerifyingKey::try_from(public_key.as_slice()).ok()?;
+                let signature = Signature::from_slice(signature).ok()?;
+
+                if !self.is_open() && !self.authorized_keys.contains(&public_key) {
+                    return None;
+                }
+
+                public_key
+                    .verify(challenge, &signature)
+                    .ok()
+                    .map(|_| Identity::Key(public_key))
+            }
+        }
+    }
+}
+
+pub(crate) fn make_challenge() -> Vec<u8> {
+    use rand::RngCore;
+
+    let mut challenge = vec![0; CHALLENGE_LEN];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    challenge
+}
+
+/// Lets a peer pick up where they left off if they lose their connection to the server.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct ResumeToken(String);
+
+impl std::fmt::Debug for ResumeToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ResumeToken(..)")
+    }
+}
+
+pub(crate) fn make_resume_token() -> ResumeToken {
+    ResumeToken(hex::encode(make_challenge()))
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Parse a hex encoded ed25519 public key.
+pub fn parse_public_key(key: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(key.trim())?
+        .try_into()
+        .map_err(|_| eyre!("public key should be 32 bytes"))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Parse a hex encoded ed25519 secret key.
+pub fn parse_signing_key(key: &str) -> Result<SigningKey> {
+    let bytes: [u8; 32] = hex::decode(key.trim())?
+        .try_into()
+        .map_err(|_| eyre!("secret key should be 32 bytes"))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
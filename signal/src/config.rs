@@ -0,0 +1,461 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::{eyre, Result};
+use serde::Deserialize;
+
+/// Settings for the signal server, read from a TOML file with flags taking priority over it.
+/// Durations are in seconds. Anything that is unset uses the [`signal::ServerOptions`] default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) listen: Vec<String>,
+    pub(crate) tls: Option<TlsConfig>,
+    pub(crate) request_ttl: Option<u64>,
+    pub(crate) resume_grace: Option<u64>,
+    pub(crate) ping_interval: Option<u64>,
+    pub(crate) idle_timeout: Option<u64>,
+    pub(crate) channel_limit: Option<usize>,
+    /// 0 to never disconnect peers for sending malformed messages.
+    pub(crate) malformed_message_limit: Option<u32>,
+    pub(crate) max_peers: Option<usize>,
+    pub(crate) max_pending_requests: Option<usize>,
+    /// In bytes.
+    pub(crate) max_message_size: Option<usize>,
+    pub(crate) max_invite_ttl: Option<u64>,
+    pub(crate) shutdown_retry_after: Option<u64>,
+    pub(crate) rate_limits: RateLimitsConfig,
+    pub(crate) ids: IdConfig,
+    pub(crate) auth: AuthConfig,
+    pub(crate) turn: Option<TurnConfig>,
+    pub(crate) admin: Option<AdminConfig>,
+    pub(crate) log: LogConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsConfig {
+    pub(crate) cert: PathBuf,
+    pub(crate) key: PathBuf,
+    /// How often to check the cert and key for changes.
+    pub(crate) reload_interval: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RateLimitsConfig {
+    pub(crate) connection_requests: Option<RateLimitConfig>,
+    pub(crate) ice_candidates: Option<RateLimitConfig>,
+    pub(crate) descriptions: Option<RateLimitConfig>,
+    pub(crate) other: Option<RateLimitConfig>,
+    /// 0 to never disconnect peers for going over their limits.
+    pub(crate) disconnect_after: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RateLimitConfig {
+    pub(crate) per_second: f64,
+    pub(crate) burst: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct IdConfig {
+    pub(crate) length: Option<usize>,
+    pub(crate) charset: Option<String>,
+    pub(crate) allow_requested: Option<bool>,
+    pub(crate) bound_id_ttl: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AuthConfig {
+    pub(crate) token: Option<String>,
+    /// Hex encoded ed25519 public keys.
+    pub(crate) authorized_keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TurnConfig {
+    pub(crate) secret: String,
+    pub(crate) urls: Vec<String>,
+    pub(crate) ttl: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AdminConfig {
+    pub(crate) address: String,
+    pub(crate) token: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    #[default]
+    Pretty,
+    Compact,
+    Json,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LogConfig {
+    pub(crate) format: LogFormat,
+    /// Filter directives like `RUST_LOG`, which takes priority over this.
+    pub(crate) level: Option<String>,
+    /// File to append audit events (who connected to whom and when) to as JSON lines.
+    pub(crate) audit: Option<PathBuf>,
+}
+
+/// Ids shorter than this are too easy to guess.
+const MIN_ID_SPACE: f64 = 1_000_000.0;
+
+impl Config {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| eyre!("unable to read config {}: {err}", path.display()))?;
+        toml::from_str(&text).map_err(|err| eyre!("invalid config {}: {err}", path.display()))
+    }
+
+    /// Check that everything makes sense, reporting every problem at once.
+    pub(crate) fn validate(self) -> Result<(Vec<String>, signal::ServerOptions)> {
+        let mut problems = vec![];
+        let mut options = signal::ServerOptions::default();
+
+        if self.listen.is_empty() {
+            problems.push("nothing to listen on, pass an address or set `listen`".to_owned());
+        }
+        for address in &self.listen {
+            if address.parse::<SocketAddr>().is_err() {
+                problems.push(format!(
+                    "listen address `{address}` should look like `0.0.0.0:8080`"
+                ));
+            }
+        }
+
+        let seconds =
+            |value: Option<u64>, default: Duration| value.map_or(default, Duration::from_secs);
+
+        if let Some(tls) = self.tls {
+            for (what, path) in [("cert", &tls.cert), ("key", &tls.key)] {
+                if !path.is_file() {
+                    problems.push(format!("tls {what} `{}` does not exist", path.display()));
+                }
+            }
+
+            let mut tls_options = signal::TlsOptions::new(tls.cert, tls.key);
+            tls_options.reload_interval = seconds(tls.reload_interval, tls_options.reload_interval);
+            if tls_options.reload_interval.is_zero() {
+                problems.push("`tls.reload_interval` should be at least 1 second".to_owned());
+            }
+            options.tls = Some(tls_options);
+        }
+
+        options.request_ttl = seconds(self.request_ttl, options.request_ttl);
+        options.resume_grace = seconds(self.resume_grace, options.resume_grace);
+        options.ping_interval = seconds(self.ping_interval, options.ping_interval);
+        options.idle_timeout = seconds(self.idle_timeout, options.idle_timeout);
+        options.max_invite_ttl = seconds(self.max_invite_ttl, options.max_invite_ttl);
+        options.shutdown_retry_after =
+            seconds(self.shutdown_retry_after, options.shutdown_retry_after);
+
+        if options.request_ttl.is_zero() {
+            problems.push("`request_ttl` should be at least 1 second".to_owned());
+        }
+        if options.max_invite_ttl < Duration::from_secs(60) {
+            problems.push("`max_invite_ttl` should be at least 60 seconds".to_owned());
+        }
+        if options.ping_interval.is_zero() {
+            problems.push("`ping_interval` should be at least 1 second".to_owned());
+        }
+        if options.idle_timeout <= options.ping_interval {
+            problems.push(format!(
+                "`idle_timeout` ({}s) should be longer than `ping_interval` ({}s)",
+                options.idle_timeout.as_secs(),
+                options.ping_interval.as_secs()
+            ));
+        }
+
+        if let Some(channel_limit) = self.channel_limit {
+            if channel_limit == 0 {
+                problems.push("`channel_limit` should be at least 1".to_owned());
+            }
+            options.channel_limit = channel_limit;
+        }
+
+        if let Some(malformed_message_limit) = self.malformed_message_limit {
+            options.malformed_message_limit =
+                Some(malformed_message_limit).filter(|limit| *limit > 0);
+        }
+
+        options.max_peers = self.max_peers;
+        if self.max_peers == Some(0) {
+            problems.push("`max_peers` should be at least 1".to_owned());
+        }
+        if let Some(max_pending_requests) = self.max_pending_requests {
+            if max_pending_requests == 0 {
+                problems.push("`max_pending_requests` should be at least 1".to_owned());
+            }
+            options.max_pending_requests = Some(max_pending_requests);
+        }
+
+        if let Some(max_message_size) = self.max_message_size {
+            if max_message_size < 1024 {
+                problems.push("`max_message_size` should be at least 1024 bytes".to_owned());
+            }
+            options.max_message_size = max_message_size;
+        }
+
+        for (name, config, limit) in [
+            (
+                "connection_requests",
+                self.rate_limits.connection_requests,
+                &mut options.rate_limits.connection_requests,
+            ),
+            (
+                "ice_candidates",
+                self.rate_limits.ice_candidates,
+                &mut options.rate_limits.ice_candidates,
+            ),
+            (
+                "descriptions",
+                self.rate_limits.descriptions,
+                &mut options.rate_limits.descriptions,
+            ),
+            (
+                "other",
+                self.rate_limits.other,
+                &mut options.rate_limits.other,
+            ),
+        ] {
+            let Some(config) = config else {
+                continue;
+            };
+            if config.per_second.is_nan() || config.per_second <= 0.0 {
+                problems.push(format!("`rate_limits.{name}.per_second` should be above 0"));
+            }
+            if config.burst == 0 {
+                problems.push(format!("`rate_limits.{name}.burst` should be at least 1"));
+            }
+            *limit = signal::RateLimit::new(config.per_second, config.burst);
+        }
+        if let Some(disconnect_after) = self.rate_limits.disconnect_after {
+            options.rate_limits.disconnect_after =
+                Some(disconnect_after).filter(|limit| *limit > 0);
+        }
+
+        if let Some(length) = self.ids.length {
+            options.ids.length = length;
+        }
+        if let Some(charset) = self.ids.charset {
+            options.ids.charset = charset;
+        }
+        if let Some(allow_requested) = self.ids.allow_requested {
+            options.ids.allow_requested = allow_requested;
+        }
+        options.ids.bound_id_ttl = seconds(self.ids.bound_id_ttl, options.ids.bound_id_ttl);
+
+        let charset: Vec<char> = options.ids.charset.chars().collect();
+        if charset.is_empty() {
+            problems.push("`ids.charset` should not be empty".to_owned());
+        } else if charset
+            .iter()
+            .any(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-' || *c == '_'))
+        {
+            problems.push(
+                "`ids.charset` should only contain lowercase letters, digits, `-` and `_`"
+                    .to_owned(),
+            );
+        } else if (1..charset.len()).any(|i| charset[..i].contains(&charset[i])) {
+            problems.push("`ids.charset` should not repeat characters".to_owned());
+        }
+        if options.ids.length > signal::MAX_REQUESTED_ID_LEN {
+            problems.push(format!(
+                "`ids.length` should be at most {}",
+                signal::MAX_REQUESTED_ID_LEN
+            ));
+        } else if (charset.len() as f64).powi(options.ids.length as i32) < MIN_ID_SPACE {
+            problems.push(format!(
+                "`ids.length` {} with {} characters gives too few ids, make one of them bigger",
+                options.ids.length,
+                charset.len()
+            ));
+        }
+
+        options.auth.token = self.auth.token;
+        if options.auth.token.as_deref() == Some("") {
+            problems.push("`auth.token` should not be empty".to_owned());
+        }
+        for key in &self.auth.authorized_keys {
+            match signal::parse_public_key(key) {
+                Ok(key) => options.auth.authorized_keys.push(key),
+                Err(err) => problems.push(format!("authorized key `{key}` is invalid: {err}")),
+            }
+        }
+
+        if let Some(turn) = self.turn {
+            if turn.secret.is_empty() {
+                problems.push("`turn.secret` should not be empty".to_owned());
+            }
+            if turn.urls.is_empty() {
+                problems.push("`turn.urls` should have at least one url".to_owned());
+            }
+            for url in &turn.urls {
+                if !(url.starts_with("turn:") || url.starts_with("turns:")) {
+                    problems.push(format!(
+                        "turn url `{url}` should start with `turn:` or `turns:`"
+                    ));
+                }
+            }
+            let ttl = seconds(turn.ttl, Duration::from_secs(24 * 60 * 60));
+            if ttl < Duration::from_secs(60) {
+                problems.push("`turn.ttl` should be at least 60 seconds".to_owned());
+            }
+            options.turn = Some(signal::TurnOptions {
+                urls: turn.urls,
+                secret: turn.secret,
+                ttl,
+            });
+        }
+
+        if let Some(admin) = self.admin {
+            if admin.address.parse::<SocketAddr>().is_err() {
+                problems.push(format!(
+                    "admin address `{}` should look like `127.0.0.1:9090`",
+                    admin.address
+                ));
+            }
+            if self.listen.contains(&admin.address) {
+                problems
+                    .push("`admin.address` should not be one of the `listen` addresses".to_owned());
+            }
+            if admin.token.as_deref() == Some("") {
+                problems.push("`admin.token` should not be empty".to_owned());
+            }
+            options.admin = Some(signal::AdminOptions {
+                address: admin.address,
+                token: admin.token,
+            });
+        }
+
+        if problems.is_empty() {
+            Ok((self.listen, options))
+        } else {
+            Err(eyre!("invalid configuration:\n  {}", problems.join("\n  ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    /// Everything that is wrong with `config`, which has to be something.
+    fn problems(config: Config) -> String {
+        match config.validate() {
+            Ok(_) => panic!("expected the config to be invalid"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let (listen, options) = parse(
+            r#"
+            listen = ["0.0.0.0:8080", "[::]:8080"]
+            request_ttl = 20
+            max_peers = 100
+
+            [rate_limits]
+            other = { per_second = 5.0, burst = 10 }
+
+            [ids]
+            length = 6
+            allow_requested = false
+
+            [auth]
+            token = "secret"
+
+            [turn]
+            secret = "shared"
+            urls = ["turn:turn.example.com:3478"]
+
+            [admin]
+            address = "127.0.0.1:9090"
+            "#,
+        )
+        .validate()
+        .unwrap();
+
+        assert_eq!(listen, ["0.0.0.0:8080", "[::]:8080"]);
+        assert_eq!(options.request_ttl, Duration::from_secs(20));
+        assert_eq!(options.max_peers, Some(100));
+        assert_eq!(options.ids.length, 6);
+        assert!(!options.ids.allow_requested);
+        assert_eq!(options.auth.token.as_deref(), Some("secret"));
+        assert_eq!(options.turn.unwrap().ttl, Duration::from_secs(24 * 60 * 60));
+        assert_eq!(options.admin.unwrap().address, "127.0.0.1:9090");
+
+        // NOTE(emily): Anything left out keeps the server's default.
+        let defaults = signal::ServerOptions::default();
+        assert_eq!(options.ping_interval, defaults.ping_interval);
+        assert_eq!(options.resume_grace, defaults.resume_grace);
+    }
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let err = problems(parse(
+            r#"
+            listen = ["localhost"]
+            request_ttl = 0
+            ping_interval = 30
+            idle_timeout = 10
+
+            [ids]
+            charset = "ABC"
+
+            [turn]
+            secret = ""
+            urls = ["stun:stun.example.com"]
+
+            [admin]
+            address = "localhost"
+            token = ""
+            "#,
+        ));
+
+        for problem in [
+            "listen address `localhost`",
+            "`request_ttl` should be at least 1 second",
+            "`idle_timeout` (10s) should be longer than `ping_interval` (30s)",
+            "`ids.charset` should only contain",
+            "`turn.secret` should not be empty",
+            "turn url `stun:stun.example.com`",
+            "admin address `localhost`",
+            "`admin.token` should not be empty",
+        ] {
+            assert!(err.contains(problem), "{problem:?} missing from {err}");
+        }
+    }
+
+    #[test]
+    fn test_nothing_to_listen_on() {
+        let err = problems(Config::default());
+        assert!(err.contains("nothing to listen on"));
+    }
+
+    #[test]
+    fn test_unknown_fields_are_refused() {
+        assert!(toml::from_str::<Config>("request_tll = 20").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::{PeerId, ServerState, SignallingError, AUDIT_TARGET};
+
+/// Lets whoever holds it connect to the peer that made it without being accepted by hand.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InviteToken(String);
+
+impl std::fmt::Debug for InviteToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "InviteToken(..)")
+    }
+}
+
+impl std::fmt::Display for InviteToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for InviteToken {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+/// What a peer that connected with an invite is allowed to do. The server only passes this on,
+/// it is up to the host to enforce it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Permission {
+    ViewOnly,
+    #[default]
+    Control,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InviteOptions {
+    /// How long the invite can be used for, the server may cut this short.
+    pub ttl: Duration,
+    /// How many connections the invite is good for, if unset it can be used until it expires.
+    pub max_uses: Option<u32>,
+    pub permission: Permission,
+}
+
+impl Default for InviteOptions {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60 * 60),
+            max_uses: Some(1),
+            permission: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Invite {
+    host: PeerId,
+    permission: Permission,
+    expires_at: Instant,
+    uses_left: Option<u32>,
+}
+
+pub(crate) type InviteMap = Arc<Mutex<HashMap<InviteToken, Invite>>>;
+
+fn make_invite_token() -> InviteToken {
+    let mut token = [0; 16];
+    rand::thread_rng().fill_bytes(&mut token);
+    InviteToken(hex::encode(token))
+}
+
+/// Make an invite to connect to `host`, returning it along with how long it lasts.
+pub(crate) async fn create_invite(
+    state: &ServerState,
+    host: &PeerId,
+    options: InviteOptions,
+) -> (InviteToken, Duration) {
+    let ttl = options.ttl.min(state.options.max_invite_ttl);
+    let token = make_invite_token();
+
+    let mut invites = state.invites.lock().await;
+
+    // NOTE(emily): Nothing else gets rid of invites that expire without being used up.
+    let now = Instant::now();
+    invites.retain(|_, invite| invite.expires_at > now);
+
+    invites.insert(
+        token.clone(),
+        Invite {
+            host: host.clone(),
+            permission: options.permission,
+            expires_at: now + ttl,
+            uses_left: options.max_uses,
+        },
+    );
+
+    tracing::info!(
+        target: AUDIT_TARGET,
+        %host,
+        permission = ?options.permission,
+        max_uses = ?options.max_uses,
+        ttl = ttl.as_secs(),
+        "invite created"
+    );
+
+    (token, ttl)
+}
+
+/// Use up `token` to connect to `host`, returning what the connection is allowed to do.
+pub(crate) async fn redeem(
+    state: &ServerState,
+    token: &InviteToken,
+    host: &PeerId,
+) -> Result<Permission, SignallingError> {
+    let mut invites = state.invites.lock().await;
+
+    let invite = invites
+        .get_mut(token)
+        .filter(|invite| {
+            &invite.host == host
+                && invite.expires_at > Instant::now()
+                && invite.uses_left != Some(0)
+        })
+        .ok_or(SignallingError::InvalidInvite)?;
+    let permission = invite.permission;
+
+    match &mut invite.uses_left {
+        Some(1) => {
+            invites.remove(token);
+        }
+        Some(uses_left) => *uses_left -= 1,
+        None => {}
+    }
+
+    Ok(permission)
+}
+
+/// Stop `token` from being used, only `host` (who made it) can do this.
+pub(crate) async fn revoke(
+    state: &ServerState,
+    token: &InviteToken,
+    host: &PeerId,
+) -> Result<(), SignallingError> {
+    let mut invites = state.invites.lock().await;
+
+    if invites
+        .get(token)
+        .is_some_and(|invite| &invite.host == host)
+    {
+        invites.remove(token);
+        tracing::info!(target: AUDIT_TARGET, %host, "invite revoked");
+        Ok(())
+    } else {
+        Err(SignallingError::InvalidInvite)
+    }
+}
+
+/// Get rid of every invite to a peer that has gone for good.
+pub(crate) async fn revoke_all(state: &ServerState, host: &PeerId) {
+    state
+        .invites
+        .lock()
+        .await
+        .retain(|_, invite| &invite.host != host);
+}
@@ -1,18 +1,55 @@
-use std::{collections::HashMap, sync::Arc};
+mod admin;
+mod auth;
+mod invite;
+mod rate_limit;
+mod relay;
+mod room;
+mod tls;
+mod transport;
+mod turn;
 
-use futures::stream::SplitSink;
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::stream::{SplitSink, SplitStream};
 use futures::SinkExt;
 use futures::{FutureExt, StreamExt, TryStreamExt};
 use serde::Deserialize;
 use serde::Serialize;
-use tokio::net::TcpStream;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Message::{self, Binary, Close, Frame, Ping, Pong, Text};
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::Instrument;
 use uuid::Uuid;
 
 use eyre::{eyre, Result};
 
+pub use admin::AdminOptions;
+use admin::{Metrics, Relayed};
+pub use auth::{parse_public_key, parse_signing_key, AuthOptions, Credentials};
+use auth::{AuthResponse, ResumeToken};
+use invite::InviteMap;
+pub use invite::{InviteOptions, InviteToken, Permission};
+use rate_limit::RateLimiter;
+pub use rate_limit::{RateLimit, RateLimits};
+use relay::ConnectedMap;
+use room::RoomMap;
+pub use room::{JoinPolicy, RoomId};
+use tls::PeerStream;
+pub use tls::TlsOptions;
+pub use tokio_rustls::rustls::pki_types::CertificateDer;
+use transport::{ClientStream, Connector};
+pub use transport::{
+    InProcessBroker, ManualTransport, Signalling, SignallingTransport, WebsocketTransport,
+};
+pub use turn::TurnOptions;
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct PeerId(String);
@@ -31,14 +68,168 @@ impl From<String> for PeerId {
 
 pub type ConnectionId = Uuid;
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PeerRole {
+    /// Shares its desktop with viewers.
+    Host,
+    #[default]
+    Viewer,
+}
+
+/// What a peer tells the server (and so everyone else) about itself.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PeerMetadata {
+    pub display_name: Option<String>,
+    pub role: PeerRole,
+    /// Free-form capabilities (e.g. `"audio"`, `"h264"`), the server does not interpret these.
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerInfo {
+    pub peer_id: PeerId,
+    pub metadata: PeerMetadata,
+}
+
+/// A STUN or TURN server that peers can use to find their way to each other.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    pub username: Option<String>,
+    pub credential: Option<String>,
+}
+
 pub(crate) const ARBITRARY_SIGNALLING_CHANNEL_LIMIT: usize = 5;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// How long a peer has to say hello and answer the authentication challenge after connecting.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bump this whenever a change to the messages would break peers on an older version. Optional
+/// additions should be a feature instead.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Optional parts of the protocol, only used if both the peer and the server know about them.
+pub mod features {
+    /// The server holds onto peers that lose their connection so that they can resume.
+    pub const RESUME: &str = "resume";
+    /// The server hands out ICE servers (with TURN credentials) to use for connections.
+    pub const ICE_SERVERS: &str = "ice-servers";
+    /// Hosts can open rooms that many viewers join.
+    pub const ROOMS: &str = "rooms";
+    /// The server says when it is going away and how long to wait before coming back.
+    pub const SHUTDOWN_NOTICE: &str = "shutdown-notice";
+
+    /// Every feature that this version knows about.
+    pub const ALL: &[&str] = &[RESUME, ICE_SERVERS, ROOMS, SHUTDOWN_NOTICE];
+}
+
+/// Features that both a peer and the server agreed on in their hello.
+pub type Features = HashSet<String>;
+
+/// How long the client waits for the server to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum SignallingError {
     NoSuchPeer(PeerId),
+    AuthenticationFailed,
+    /// The requested id is in use by another peer or bound to a different key.
+    IdTaken(PeerId),
+    /// The requested id is not a valid peer id.
+    InvalidId(PeerId),
+    /// The peer speaks a different protocol version to the server, which speaks this one.
+    IncompatibleVersion(u32),
+    /// The server already has as many peers as it is allowed.
+    ServerFull,
+    /// The peer already has as many connection requests pending as it is allowed.
+    TooManyPendingRequests,
+    CannotConnectToSelf,
+    /// The server did not answer a request in time.
+    TimedOut,
+    /// The connection to the server went away before a request was answered.
+    Disconnected,
+    /// A text frame that did not parse as a message.
+    MalformedMessage,
+    /// A frame that was not text.
+    UnsupportedFrame,
+    /// A message bigger than the server's `max_message_size`.
+    MessageTooLarge,
+    /// Too many messages of this kind, slow down.
+    RateLimited,
+    /// An admin told the server to disconnect this peer.
+    Kicked,
+    NoSuchRoom(RoomId),
+    /// The room is invite only and the host has not invited this peer.
+    NotInvited,
+    /// Only the host of a room can do that.
+    NotRoomHost,
+    /// The invite is unknown, expired, used up or for a different peer.
+    InvalidInvite,
+    /// Offers, answers and candidates only go to peers that accepted a connection with us.
+    NotConnected(PeerId),
+    /// The peer is not keeping up with the messages that are already waiting for them, try again
+    /// later.
+    PeerBusy(PeerId),
+    /// An offer, answer or candidate that is malformed or too big.
+    InvalidPayload,
+    /// The signalling transport cannot do that, see [`SignallingTransport`].
+    Unsupported,
     InternalError,
 }
 
+impl std::fmt::Display for SignallingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSuchPeer(peer_id) => write!(f, "no such peer {peer_id}"),
+            Self::AuthenticationFailed => write!(f, "authentication failed"),
+            Self::IdTaken(peer_id) => write!(f, "id {peer_id} is already taken"),
+            Self::InvalidId(peer_id) => write!(f, "id {peer_id} is not a valid id"),
+            Self::ServerFull => write!(f, "server is full"),
+            Self::TooManyPendingRequests => write!(f, "too many pending connection requests"),
+            Self::IncompatibleVersion(version) => {
+                write!(f, "incompatible protocol version, server speaks {version}")
+            }
+            Self::CannotConnectToSelf => write!(f, "cannot connect to self"),
+            Self::TimedOut => write!(f, "timed out waiting for the server"),
+            Self::Disconnected => write!(f, "disconnected from the server"),
+            Self::MalformedMessage => write!(f, "malformed message"),
+            Self::UnsupportedFrame => write!(f, "unsupported frame"),
+            Self::MessageTooLarge => write!(f, "message too large"),
+            Self::RateLimited => write!(f, "rate limited"),
+            Self::Kicked => write!(f, "kicked by the server"),
+            Self::NoSuchRoom(room_id) => write!(f, "no such room {room_id}"),
+            Self::NotInvited => write!(f, "not invited to the room"),
+            Self::NotRoomHost => write!(f, "not the host of the room"),
+            Self::InvalidInvite => write!(f, "invalid invite"),
+            Self::NotConnected(peer_id) => write!(f, "not connected to {peer_id}"),
+            Self::PeerBusy(peer_id) => write!(f, "{peer_id} is busy, try again later"),
+            Self::InvalidPayload => write!(f, "invalid offer, answer or candidate"),
+            Self::Unsupported => write!(f, "unsupported by this transport"),
+            Self::InternalError => write!(f, "internal error"),
+        }
+    }
+}
+
+impl std::error::Error for SignallingError {}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionReason {
+    /// The requestee declined the connection.
+    Declined,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RequestClosedReason {
+    /// Nobody responded to the request in time.
+    Expired,
+    /// The requester withdrew the request.
+    Cancelled,
+    /// One of the peers went away whilst the request was pending.
+    PeerDisconnected,
+}
+
+/// `job_id` ties a response to the request that caused it. Messages that are not a response to
+/// anything (and requests that do not care about their response) use a `job_id` of 0.
 #[derive(Debug, Serialize, Deserialize)]
 struct PeerToServerMessage {
     job_id: usize,
@@ -53,512 +244,4050 @@ struct ServerToPeerMessage {
 
 #[derive(Debug, Serialize, Deserialize)]
 enum PeerToServer {
+    /// The first thing that a peer sends. NOTE(emily): This must never change shape, it is how
+    /// mismatched versions find out about each other.
+    Hello {
+        protocol_version: u32,
+        features: Vec<String>,
+    },
+    /// Authenticate, optionally asking for a specific id or to resume a previous session.
+    Authenticate {
+        response: AuthResponse,
+        peer_id: Option<PeerId>,
+        metadata: PeerMetadata,
+        resume_token: Option<ResumeToken>,
+    },
+    ListPeers,
     IceCandidate(PeerId, String),
     Offer(PeerId, String),
     Answer(PeerId, String),
-    ConnectToPeer(PeerId),
+    /// Ask to connect to a peer, which is accepted straight away if it comes with one of their
+    /// invites.
+    ConnectToPeer(PeerId, Option<InviteToken>),
     AcceptConnection(ConnectionId),
+    RejectConnection(ConnectionId),
+    CancelConnection(ConnectionId),
+    /// Open a room that we host, answered with [`ServerToPeer::RoomCreated`].
+    CreateRoom(JoinPolicy),
+    /// Answered with [`ServerToPeer::RoomJoined`] if we are let straight in, or
+    /// [`ServerToPeer::RoomJoinRequested`] if the host has to approve it first.
+    JoinRoom(RoomId),
+    /// Leave a room, closing it if we are the host.
+    LeaveRoom(RoomId),
+    /// Let a peer into an invite only room (or skip approval for them).
+    InviteToRoom(RoomId, PeerId),
+    SetRoomPolicy(RoomId, JoinPolicy),
+    /// Answered with [`ServerToPeer::InviteCreated`].
+    CreateInvite(InviteOptions),
+    RevokeInvite(InviteToken),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl PeerToServer {
+    /// What sort of message this is, for logging.
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Hello { .. } => "hello",
+            Self::Authenticate { .. } => "authenticate",
+            Self::ListPeers => "list_peers",
+            Self::IceCandidate(..) => "ice_candidate",
+            Self::Offer(..) => "offer",
+            Self::Answer(..) => "answer",
+            Self::ConnectToPeer(..) => "connect_to_peer",
+            Self::AcceptConnection(_) => "accept_connection",
+            Self::RejectConnection(_) => "reject_connection",
+            Self::CancelConnection(_) => "cancel_connection",
+            Self::CreateRoom(_) => "create_room",
+            Self::JoinRoom(_) => "join_room",
+            Self::LeaveRoom(_) => "leave_room",
+            Self::InviteToRoom(..) => "invite_to_room",
+            Self::SetRoomPolicy(..) => "set_room_policy",
+            Self::CreateInvite(_) => "create_invite",
+            Self::RevokeInvite(_) => "revoke_invite",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 enum ServerToPeer {
-    Id(PeerId),
+    /// Answer to a peer's hello with the features that we agreed on. Like
+    /// [`PeerToServer::Hello`] this must never change shape.
+    Hello {
+        protocol_version: u32,
+        features: Vec<String>,
+    },
+    AuthChallenge(Vec<u8>),
+    /// Sent once a peer has authenticated. `resumed` is set if this is a previous session that
+    /// has been picked back up.
+    Id {
+        peer_id: PeerId,
+        resume_token: ResumeToken,
+        resumed: bool,
+    },
     ConnectionRequest(PeerId, ConnectionId),
+    ConnectionRequested(PeerId, ConnectionId),
+    ConnectionRequestClosed(PeerId, ConnectionId, RequestClosedReason),
     ConnectionAccepted(PeerId, ConnectionId),
+    ConnectionRejected(PeerId, ConnectionId, RejectionReason),
     Offer(PeerId, String),
     Answer(PeerId, String),
     IceCandidate(PeerId, String),
+    Peers(Vec<PeerInfo>),
+    PeerJoined(PeerInfo),
+    PeerLeft(PeerId),
+    /// ICE servers to use from now on, sent again before any credentials in them expire.
+    IceServers(Vec<IceServer>),
+    RoomCreated(RoomId),
+    /// The host has been asked to let us in, they answer it like any other connection request.
+    RoomJoinRequested(RoomId, ConnectionId),
+    /// Sent to the host when a peer asks to join a room that needs approval.
+    RoomJoinRequest(RoomId, PeerId, ConnectionId),
+    /// We are in the room, with this host.
+    RoomJoined(RoomId, PeerId),
+    /// Sent to everyone already in the room (including the host) when someone new is let in.
+    RoomMemberJoined(RoomId, PeerInfo),
+    RoomMemberLeft(RoomId, PeerId),
+    /// The host of the room invited us in.
+    RoomInvitation(RoomId, PeerId),
+    /// The host left, so everyone else is out too.
+    RoomClosed(RoomId),
+    /// A new invite and how long it lasts for.
+    InviteCreated(InviteToken, Duration),
+    /// Sent instead of [`ServerToPeer::ConnectionRequest`] when a peer connects with one of our
+    /// invites, the connection has already been accepted.
+    InvitedConnection(PeerId, ConnectionId, Permission),
+    /// The server is about to close every connection, reconnect no sooner than this.
+    ServerShuttingDown {
+        retry_after: Duration,
+    },
     Error(SignallingError),
 }
 
-fn make_id() -> PeerId {
-    use rand::Rng;
-    use std::iter;
+pub const MAX_REQUESTED_ID_LEN: usize = 32;
+
+/// How the server hands out ids.
+#[derive(Debug, Clone)]
+pub struct IdPolicy {
+    /// Length of randomly generated ids.
+    pub length: usize,
+    /// Characters that randomly generated ids are made of.
+    pub charset: String,
+    /// Whether peers can ask for a specific id.
+    pub allow_requested: bool,
+    /// How long an id claimed with a key stays reserved for that key after its peer leaves.
+    pub bound_id_ttl: Duration,
+}
+
+impl Default for IdPolicy {
+    fn default() -> Self {
+        Self {
+            length: 5,
+            charset: "abcdefghijklmnopqrstuvwxyz23456789".to_owned(),
+            allow_requested: true,
+            bound_id_ttl: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
 
-    const LEN: usize = 5;
+fn make_id(policy: &IdPolicy) -> PeerId {
+    use rand::seq::SliceRandom;
+    use std::iter;
 
-    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz23456789";
+    let charset: Vec<char> = policy.charset.chars().collect();
     let mut rng = rand::thread_rng();
-    let one_char = || CHARSET[rng.gen_range(0..CHARSET.len())] as char;
-    PeerId(iter::repeat_with(one_char).take(LEN).collect())
+    let one_char = || *charset.choose(&mut rng).unwrap();
+    PeerId(iter::repeat_with(one_char).take(policy.length).collect())
+}
+
+/// Requested ids are lowercase alphanumerics, `-` and `_`.
+fn valid_requested_id(peer_id: &PeerId) -> bool {
+    !peer_id.0.is_empty()
+        && peer_id.0.len() <= MAX_REQUESTED_ID_LEN
+        && peer_id
+            .0
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-' || c == b'_')
 }
 
 fn make_connection_id() -> ConnectionId {
     Uuid::new_v4()
 }
 
-type PeerMap = Arc<Mutex<HashMap<PeerId, mpsc::Sender<ServerToPeerMessage>>>>;
+#[derive(Debug, Clone)]
+struct Peer {
+    tx: mpsc::Sender<ServerToPeerMessage>,
+    metadata: PeerMetadata,
+    /// Where they connected from most recently.
+    address: std::net::SocketAddr,
+    connected_at: std::time::SystemTime,
+    /// Tells their connection to close for good.
+    kick: Arc<tokio::sync::Notify>,
+}
+
+type PeerMap = Arc<Mutex<HashMap<PeerId, Peer>>>;
+
+/// How a pending connection request was resolved.
+#[derive(Debug)]
+enum ConnectionResponse {
+    Accept,
+    Reject,
+    Close(RequestClosedReason),
+}
 
 #[derive(Debug)]
 struct ConnectionRequest {
-    accept: tokio::sync::oneshot::Sender<()>,
-    reject: tokio::sync::oneshot::Sender<()>,
+    response: tokio::sync::oneshot::Sender<ConnectionResponse>,
     requester: PeerId,
     requestee: PeerId,
+    requested_at: std::time::SystemTime,
+    /// Set if this is a request to join a room that `requestee` hosts.
+    room: Option<RoomId>,
 }
 
 type ConnectionRequestMap = Arc<Mutex<HashMap<ConnectionId, ConnectionRequest>>>;
 
-async fn handle_incoming_message_inner(
-    our_peer_id: PeerId,
-    connection_requests: ConnectionRequestMap,
-    peers: PeerMap,
-    message: PeerToServerMessage,
-) -> core::result::Result<(), SignallingError> {
-    tracing::debug!("{message:?}");
-    match message.inner {
-        PeerToServer::IceCandidate(peer_id, inner) => {
-            if let Some(peer) = peers.lock().await.get(&peer_id) {
-                Ok(peer
-                    .send(ServerToPeerMessage {
-                        job_id: 0,
-                        inner: ServerToPeer::IceCandidate(our_peer_id, inner),
-                    })
-                    .await
-                    .map_err(|err| SignallingError::InternalError)?)
-            } else {
-                Err(SignallingError::NoSuchPeer(peer_id))
-            }
-        }
-        PeerToServer::Offer(peer_id, inner) => {
-            if let Some(peer) = peers.lock().await.get(&peer_id) {
-                Ok(peer
-                    .send(ServerToPeerMessage {
-                        job_id: 0,
-                        inner: ServerToPeer::Offer(our_peer_id, inner),
-                    })
-                    .await
-                    .map_err(|err| SignallingError::InternalError)?)
-            } else {
-                Err(SignallingError::NoSuchPeer(peer_id))
-            }
+/// An id that has been claimed by a peer that authenticated with a key. Only that key can use the
+/// id again.
+struct BoundId {
+    key: ed25519_dalek::VerifyingKey,
+    /// When its peer went for good, the id is free again [`IdPolicy::bound_id_ttl`] after this.
+    left_at: Option<tokio::time::Instant>,
+}
+
+type BoundIdMap = Arc<Mutex<HashMap<PeerId, BoundId>>>;
+
+/// A peer whose websocket went away. They stay in the peer map (and their messages queue up)
+/// until they come back with their resume token or `resume_grace` runs out.
+#[derive(Debug)]
+struct SuspendedPeer {
+    peer_id: PeerId,
+    tx: mpsc::Sender<ServerToPeerMessage>,
+    rx: mpsc::Receiver<ServerToPeerMessage>,
+}
+
+type SuspendedPeerMap = Arc<Mutex<HashMap<ResumeToken, SuspendedPeer>>>;
+
+/// Target of the events that record who connected, who they asked to connect to and how that
+/// went. Route it somewhere durable to keep an audit log.
+pub const AUDIT_TARGET: &str = "signal::audit";
+
+pub struct ServerOptions {
+    /// How long a connection request can stay pending before it expires.
+    pub request_ttl: Duration,
+    /// How often peers are pinged.
+    pub ping_interval: Duration,
+    /// Disconnect peers that we have not heard anything from (including pongs) for this long.
+    pub idle_timeout: Duration,
+    /// How many messages can queue up for a peer before whoever is sending to them has to wait.
+    pub channel_limit: usize,
+    pub ids: IdPolicy,
+    pub max_peers: Option<usize>,
+    /// How many connection requests a single peer can have pending at once.
+    pub max_pending_requests: Option<usize>,
+    /// How long a peer that lost its connection has to come back before it is forgotten.
+    pub resume_grace: Duration,
+    /// Disconnect a peer once it has sent this many malformed, unsupported or oversized frames, if
+    /// unset they only get an error back.
+    pub malformed_message_limit: Option<u32>,
+    /// Messages bigger than this (in bytes) are refused.
+    pub max_message_size: usize,
+    pub rate_limits: RateLimits,
+    pub auth: AuthOptions,
+    /// Hand out credentials for this TURN server to peers that log in.
+    pub turn: Option<TurnOptions>,
+    /// Terminate TLS ourselves instead of relying on a reverse proxy.
+    pub tls: Option<TlsOptions>,
+    pub admin: Option<AdminOptions>,
+    /// Invites that peers make last for this long at most.
+    pub max_invite_ttl: Duration,
+    /// When shutting down, peers are told to wait this long before reconnecting.
+    pub shutdown_retry_after: Duration,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            request_ttl: Duration::from_secs(60),
+            ping_interval: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(90),
+            channel_limit: ARBITRARY_SIGNALLING_CHANNEL_LIMIT,
+            ids: Default::default(),
+            max_peers: None,
+            max_pending_requests: Some(10),
+            resume_grace: Duration::from_secs(30),
+            malformed_message_limit: Some(5),
+            max_message_size: 64 * 1024,
+            rate_limits: Default::default(),
+            auth: Default::default(),
+            turn: None,
+            tls: None,
+            admin: None,
+            max_invite_ttl: Duration::from_secs(24 * 60 * 60),
+            shutdown_retry_after: Duration::from_secs(5),
         }
-        PeerToServer::Answer(peer_id, inner) => {
-            if let Some(peer) = peers.lock().await.get(&peer_id) {
-                Ok(peer
-                    .send(ServerToPeerMessage {
-                        job_id: 0,
-                        inner: ServerToPeer::Answer(our_peer_id, inner),
-                    })
-                    .await
-                    .map_err(|err| SignallingError::InternalError)?)
-            } else {
-                Err(SignallingError::NoSuchPeer(peer_id))
-            }
+    }
+}
+
+#[derive(Clone)]
+struct ServerState {
+    peers: PeerMap,
+    connection_requests: ConnectionRequestMap,
+    bound_ids: BoundIdMap,
+    suspended_peers: SuspendedPeerMap,
+    rooms: RoomMap,
+    invites: InviteMap,
+    connected: ConnectedMap,
+    /// Set once the server is shutting down, every connection closes when it is.
+    shutdown: Arc<tokio::sync::watch::Sender<bool>>,
+    metrics: Arc<Metrics>,
+    options: Arc<ServerOptions>,
+}
+
+impl ServerState {
+    fn new(options: ServerOptions) -> Self {
+        Self {
+            peers: Default::default(),
+            connection_requests: Default::default(),
+            bound_ids: Default::default(),
+            suspended_peers: Default::default(),
+            rooms: Default::default(),
+            invites: Default::default(),
+            connected: Default::default(),
+            shutdown: Arc::new(tokio::sync::watch::Sender::new(false)),
+            metrics: Default::default(),
+            options: Arc::new(options),
         }
-        PeerToServer::ConnectToPeer(peer_id) => {
-            if peer_id == our_peer_id {
-                tracing::debug!("ignoring self connection {peer_id}");
-                Ok(())
-            } else if let Some(peer) = peers.lock().await.get(&peer_id) {
-                let connection_id = make_connection_id();
+    }
+}
 
-                let (accept_tx, accept_rx) = tokio::sync::oneshot::channel();
-                let (reject_tx, reject_rx) = tokio::sync::oneshot::channel();
-
-                let a_peers = peers.clone();
-
-                tokio::spawn({
-                    let peer_id = peer_id.clone();
-                    let our_peer_id = our_peer_id.clone();
-                    async move {
-                        let peers = a_peers;
-                        if let Ok(_) = accept_rx.await {
-                            let peers = peers.lock().await;
-                            let requester = peers.get(&our_peer_id);
-                            let requestee = peers.get(&peer_id);
-
-                            if requester.is_none() || requestee.is_none() {
-                                tracing::debug!("while accepting connection, peer disapeared {our_peer_id} {peer_id}");
-                            } else {
-                                tracing::debug!("accepting connection {connection_id} {peer_id}");
-                                requester
-                                    .unwrap()
-                                    .send(ServerToPeerMessage {
-                                        job_id: 0,
-                                        inner: ServerToPeer::ConnectionAccepted(
-                                            peer_id,
-                                            connection_id,
-                                        ),
-                                    })
-                                    .await
-                                    .unwrap();
-                                // requestee
-                                //     .unwrap()
-                                //     .send(ServerToPeerMessage {
-                                //         job_id: 0,
-                                //         inner: ServerToPeer::ConnectionAccepted(peer_id, connection_id),
-                                //     })
-                                //     .await
-                                //     .unwrap();
-                            }
-                        }
-                    }
-                });
+/// Give a newly authenticated peer an id, either the one that they asked for or a random one,
+/// and add them to the peer map.
+async fn register_peer(
+    state: &ServerState,
+    identity: &auth::Identity,
+    requested_id: Option<PeerId>,
+    peer: Peer,
+) -> core::result::Result<PeerId, SignallingError> {
+    let mut bound_ids = state.bound_ids.lock().await;
+    let mut peers = state.peers.lock().await;
 
-                let r_peers = peers.clone();
+    bound_ids.retain(|_, bound_id| {
+        bound_id
+            .left_at
+            .is_none_or(|left_at| left_at.elapsed() < state.options.ids.bound_id_ttl)
+    });
 
-                tokio::spawn(async move {
-                    let _peers = r_peers;
-                    if let Ok(_) = reject_rx.await {
-                        todo!();
-                    }
-                });
+    if state
+        .options
+        .max_peers
+        .is_some_and(|max_peers| peers.len() >= max_peers)
+    {
+        return Err(SignallingError::ServerFull);
+    }
 
-                connection_requests.lock().await.insert(
-                    connection_id,
-                    ConnectionRequest {
-                        accept: accept_tx,
-                        reject: reject_tx,
-                        requester: our_peer_id.clone(),
-                        requestee: peer_id.clone(),
-                    },
-                );
+    let peer_id = match requested_id {
+        Some(peer_id) => {
+            if !state.options.ids.allow_requested || !valid_requested_id(&peer_id) {
+                return Err(SignallingError::InvalidId(peer_id));
+            }
 
-                Ok(peer
-                    .send(ServerToPeerMessage {
-                        job_id: message.job_id,
-                        inner: ServerToPeer::ConnectionRequest(our_peer_id, connection_id),
-                    })
-                    .await
-                    .map_err(|err| SignallingError::InternalError)?)
-            } else {
-                Err(SignallingError::NoSuchPeer(peer_id))
+            if peers.contains_key(&peer_id) {
+                return Err(SignallingError::IdTaken(peer_id));
             }
-        }
-        PeerToServer::AcceptConnection(connection_id) => {
-            let mut connection_requests = connection_requests.lock().await;
-            if let Some(ConnectionRequest { requestee, .. }) =
-                connection_requests.get(&connection_id)
-            {
-                if requestee != &our_peer_id {
-                    tracing::debug!("ignoring accept connection from different requestee");
-                    return Ok(());
-                    // todo!();
+
+            match (bound_ids.get_mut(&peer_id), identity) {
+                (Some(bound_id), auth::Identity::Key(key)) if bound_id.key == *key => {
+                    bound_id.left_at = None;
                 }
-            } else {
-                tracing::debug!("ignoring accept connection for unknown connection id");
-                return Ok(());
+                (Some(_), _) => return Err(SignallingError::IdTaken(peer_id)),
+                (None, auth::Identity::Key(key)) => {
+                    bound_ids.insert(
+                        peer_id.clone(),
+                        BoundId {
+                            key: *key,
+                            left_at: None,
+                        },
+                    );
+                }
+                (None, _) => {}
+            }
+
+            peer_id
+        }
+        None => loop {
+            let peer_id = make_id(&state.options.ids);
+            if !peers.contains_key(&peer_id) && !bound_ids.contains_key(&peer_id) {
+                break peer_id;
             }
+        },
+    };
 
-            let connection_request = connection_requests.remove(&connection_id).unwrap();
+    peers.insert(peer_id.clone(), peer);
 
-            Ok(connection_request
-                .accept
-                .send(())
-                .map_err(|err| SignallingError::InternalError)?)
+    Ok(peer_id)
+}
+
+/// Wait for room in a peer's channel and send them a message. Only for background tasks, anything
+/// running on a peer's connection uses [`try_send_to_peer`] because the peer that it is waiting on
+/// could be waiting on it.
+async fn send_to_peer(peers: &PeerMap, peer_id: &PeerId, inner: ServerToPeer) {
+    let tx = peers.lock().await.get(peer_id).map(|peer| peer.tx.clone());
+    if let Some(tx) = tx {
+        if let Err(err) = tx.send(ServerToPeerMessage { job_id: 0, inner }).await {
+            tracing::debug!("unable to send to {peer_id}: {err}");
         }
+    } else {
+        tracing::debug!("peer disapeared {peer_id}");
     }
 }
 
-async fn handle_incoming_text_message(
-    our_peer_id: PeerId,
-    connection_requests: ConnectionRequestMap,
-    peers: PeerMap,
-    msg: String,
-) -> core::result::Result<(), Option<ServerToPeerMessage>> {
-    let message = serde_json::from_str::<PeerToServerMessage>(&msg).unwrap();
-    let job_id = message.job_id;
-    Ok(
-        handle_incoming_message_inner(our_peer_id, connection_requests, peers, message)
-            .await
-            .map_err(|err| ServerToPeerMessage {
-                job_id: job_id,
-                inner: ServerToPeer::Error(err),
-            })?,
-    )
+/// Queue a message for another peer without waiting, if their channel is full they are busy.
+pub(crate) fn try_send(
+    state: &ServerState,
+    peer_id: &PeerId,
+    tx: &mpsc::Sender<ServerToPeerMessage>,
+    inner: ServerToPeer,
+) -> core::result::Result<(), SignallingError> {
+    tx.try_send(ServerToPeerMessage { job_id: 0, inner })
+        .map_err(|err| match err {
+            mpsc::error::TrySendError::Full(_) => {
+                tracing::warn!(%peer_id, "dropping message, channel is full");
+                state
+                    .metrics
+                    .dropped_messages
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                SignallingError::PeerBusy(peer_id.clone())
+            }
+            mpsc::error::TrySendError::Closed(_) => SignallingError::NoSuchPeer(peer_id.clone()),
+        })
 }
 
-async fn handle_incoming_message(
-    our_peer_id: PeerId,
-    outgoing: &mut SplitSink<WebSocketStream<TcpStream>, Message>,
-    connection_requests: ConnectionRequestMap,
-    peers: PeerMap,
-    msg: Message,
-) -> core::result::Result<(), Option<ServerToPeerMessage>> {
-    match msg {
-        Text(text_message) => {
-            handle_incoming_text_message(our_peer_id, connection_requests, peers, text_message)
-                .await
+/// [`try_send`] to a peer by id.
+pub(crate) async fn try_send_to_peer(
+    state: &ServerState,
+    peer_id: &PeerId,
+    inner: ServerToPeer,
+) -> core::result::Result<(), SignallingError> {
+    let tx = state
+        .peers
+        .lock()
+        .await
+        .get(peer_id)
+        .map(|peer| peer.tx.clone())
+        .ok_or_else(|| SignallingError::NoSuchPeer(peer_id.clone()))?;
+
+    try_send(state, peer_id, &tx, inner)
+}
+
+/// Send a message to every peer except `except`.
+async fn broadcast(state: &ServerState, except: &PeerId, inner: ServerToPeer) {
+    // NOTE(emily): Take the senders out of the peer map first so that a peer with a full channel
+    // cannot block everyone else from using it.
+    let txs: Vec<_> = state
+        .peers
+        .lock()
+        .await
+        .iter()
+        .filter(|(peer_id, _)| *peer_id != except)
+        .map(|(peer_id, peer)| (peer_id.clone(), peer.tx.clone()))
+        .collect();
+
+    for (peer_id, tx) in txs {
+        // NOTE(emily): Suspended peers are not reading their channel, don't wait on them.
+        if let Err(err) = tx.try_send(ServerToPeerMessage {
+            job_id: 0,
+            inner: inner.clone(),
+        }) {
+            tracing::warn!(%peer_id, "dropping broadcast: {err}");
+            state
+                .metrics
+                .dropped_messages
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         }
-        Binary(_) | Frame(_) => panic!("No idea what to do with binary"),
-        Close(_) => {
-            println!("{} i close", our_peer_id);
-            Err(None)
+    }
+}
+
+/// Take the connection request `connection_id` out of `connection_requests` if `allowed` says
+/// that the peer responding to it is allowed to.
+async fn take_connection_request(
+    connection_requests: &ConnectionRequestMap,
+    connection_id: &ConnectionId,
+    allowed: impl FnOnce(&ConnectionRequest) -> bool,
+) -> Option<ConnectionRequest> {
+    let mut connection_requests = connection_requests.lock().await;
+    match connection_requests.get(connection_id) {
+        Some(connection_request) if !allowed(connection_request) => {
+            tracing::debug!("ignoring connection response from different peer");
+            None
+        }
+        Some(_) => connection_requests.remove(connection_id),
+        None => {
+            tracing::debug!("ignoring connection response for unknown connection id");
+            None
         }
-        Ping(data) => Ok(outgoing
-            .send(tokio_tungstenite::tungstenite::Message::Pong(data))
-            .await
-            .unwrap()),
-        Pong(_data) => Ok(()),
     }
 }
 
-async fn handle_outgoing(
-    outgoing: &mut SplitSink<WebSocketStream<TcpStream>, Message>,
-    msg: ServerToPeerMessage,
-) -> Result<()> {
-    let text = serde_json::to_string(&msg)?;
-    let message = tokio_tungstenite::tungstenite::Message::text(text);
-    outgoing.send(message).await?;
+/// Wait for a connection request to be resolved (or to expire) and tell the peers involved.
+async fn connection_request_task(
+    state: ServerState,
+    connection_id: ConnectionId,
+    requester: PeerId,
+    requestee: PeerId,
+    room: Option<RoomId>,
+    mut response_rx: tokio::sync::oneshot::Receiver<ConnectionResponse>,
+) {
+    let response = tokio::select! {
+        response = &mut response_rx => response,
+        _ = tokio::time::sleep(state.options.request_ttl) => {
+            if state.connection_requests.lock().await.remove(&connection_id).is_some() {
+                Ok(ConnectionResponse::Close(RequestClosedReason::Expired))
+            } else {
+                // NOTE(emily): Someone took the request whilst we were timing out, their response
+                // is on its way.
+                response_rx.await
+            }
+        }
+    };
 
-    eyre::Ok(())
+    let peers = &state.peers;
+
+    match response {
+        Ok(ConnectionResponse::Accept) => {
+            tracing::info!(
+                target: AUDIT_TARGET,
+                %connection_id,
+                %requester,
+                %requestee,
+                "connection accepted"
+            );
+            state.metrics.relayed(Relayed::ConnectionAccepted);
+            match room {
+                // NOTE(emily): Being let into the room is how they find out.
+                Some(room_id) => {
+                    match room::admit(&state, &room_id, &requester, connection_id).await {
+                        Ok(Some(joined)) => send_to_peer(peers, &requester, joined).await,
+                        Ok(None) => {}
+                        Err(err) => {
+                            tracing::debug!(%connection_id, %err, "unable to let peer into room");
+                            send_to_peer(peers, &requester, ServerToPeer::Error(err)).await;
+                        }
+                    }
+                }
+                None => {
+                    relay::connect(&state, &requester, &requestee, connection_id).await;
+                    send_to_peer(
+                        peers,
+                        &requester,
+                        ServerToPeer::ConnectionAccepted(requestee, connection_id),
+                    )
+                    .await;
+                }
+            }
+        }
+        Ok(ConnectionResponse::Reject) => {
+            tracing::info!(
+                target: AUDIT_TARGET,
+                %connection_id,
+                %requester,
+                %requestee,
+                "connection rejected"
+            );
+            state.metrics.relayed(Relayed::ConnectionRejected);
+            send_to_peer(
+                peers,
+                &requester,
+                ServerToPeer::ConnectionRejected(
+                    requestee,
+                    connection_id,
+                    RejectionReason::Declined,
+                ),
+            )
+            .await;
+        }
+        Ok(ConnectionResponse::Close(reason)) => {
+            tracing::info!(
+                target: AUDIT_TARGET,
+                %connection_id,
+                %requester,
+                %requestee,
+                ?reason,
+                "connection request closed"
+            );
+            send_to_peer(
+                peers,
+                &requester,
+                ServerToPeer::ConnectionRequestClosed(requestee.clone(), connection_id, reason),
+            )
+            .await;
+            send_to_peer(
+                peers,
+                &requestee,
+                ServerToPeer::ConnectionRequestClosed(requester, connection_id, reason),
+            )
+            .await;
+        }
+        Err(_) => {
+            tracing::debug!(%connection_id, "connection request dropped without a response");
+        }
+    }
 }
 
-pub async fn server(address: &str) -> Result<()> {
-    let peers: PeerMap = Default::default();
-    let connection_requests: ConnectionRequestMap = Default::default();
-    let listener = tokio::net::TcpListener::bind(address).await?;
+/// Make a connection request from `requester` to `requestee`, which is resolved in the background
+/// by [`connection_request_task`]. It is up to the caller to tell `requestee` about it.
+async fn open_connection_request(
+    state: &ServerState,
+    requester: &PeerId,
+    requestee: &PeerId,
+    room: Option<RoomId>,
+) -> core::result::Result<ConnectionId, SignallingError> {
+    let mut connection_requests = state.connection_requests.lock().await;
 
-    while let Ok((conn, addr)) = listener.accept().await {
-        let peers = peers.clone();
-        let connection_requests = connection_requests.clone();
-        tokio::spawn(async move {
-            println!("i {}", addr);
+    let pending_requests = connection_requests
+        .values()
+        .filter(|request| &request.requester == requester)
+        .count();
+    if state
+        .options
+        .max_pending_requests
+        .is_some_and(|max_pending_requests| pending_requests >= max_pending_requests)
+    {
+        return Err(SignallingError::TooManyPendingRequests);
+    }
 
-            let ws_stream = tokio_tungstenite::accept_async(conn).await?;
+    let connection_id = make_connection_id();
+    let (response_tx, response_rx) = tokio::sync::oneshot::channel();
 
-            let peer_id = make_id();
+    connection_requests.insert(
+        connection_id,
+        ConnectionRequest {
+            response: response_tx,
+            requester: requester.clone(),
+            requestee: requestee.clone(),
+            requested_at: std::time::SystemTime::now(),
+            room: room.clone(),
+        },
+    );
 
-            println!("{} {}", peer_id, addr);
+    tokio::spawn(connection_request_task(
+        state.clone(),
+        connection_id,
+        requester.clone(),
+        requestee.clone(),
+        room,
+        response_rx,
+    ));
 
-            let (mut outgoing, mut incoming) = ws_stream.split();
-            let (tx, mut rx) = mpsc::channel(ARBITRARY_SIGNALLING_CHANNEL_LIMIT);
+    state.metrics.relayed(Relayed::ConnectionRequest);
 
-            peers.lock().await.insert(peer_id.clone(), tx.clone());
+    Ok(connection_id)
+}
 
-            tx.send(ServerToPeerMessage {
-                job_id: 0,
-                inner: ServerToPeer::Id(peer_id.clone()),
-            })
-            .await
-            .unwrap();
+/// Forget about a peer that has gone for good.
+async fn remove_peer(state: &ServerState, peer_id: &PeerId) {
+    tracing::info!(target: AUDIT_TARGET, %peer_id, "peer left");
+    state.peers.lock().await.remove(peer_id);
+    if let Some(bound_id) = state.bound_ids.lock().await.get_mut(peer_id) {
+        bound_id.left_at = Some(tokio::time::Instant::now());
+    }
+    close_connection_requests_for_peer(state, peer_id).await;
+    room::leave_all(state, peer_id).await;
+    invite::revoke_all(state, peer_id).await;
+    relay::disconnect_all(state, peer_id).await;
+    broadcast(state, peer_id, ServerToPeer::PeerLeft(peer_id.clone())).await;
+}
 
-            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(30));
-
-            loop {
-                let peers = peers.clone();
-                let connection_requests = connection_requests.clone();
-                futures::select! {
-                    msg = incoming.next().fuse() => {
-                        match msg {
-                            Some(Ok(msg)) => {
-                                match handle_incoming_message(peer_id.clone(), &mut outgoing, connection_requests, peers, msg).await {
-                                    Err(Some(response)) => {
-                                        tx.send(response).await?
-                                    }
-                                    Err(None) => {
-                                        println!("{} d err None", peer_id);
-                                        break;
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            None => {
-                                println!("{} d None", peer_id);
-                                break;
-                            }
-                            Some(Err(err)) => {
-                                println!("{} d err {err}", peer_id);
-                                break;
-                            },
-                        }
-                    },
-                    msg = rx.recv().fuse() => {
-                        match msg {
-                            Some(msg) => {
-                                handle_outgoing(&mut outgoing, msg).await?
-                            }
-                            None => {
-                                println!("{} d outgoing None", peer_id);
-                                break;
-                            },
-                        }
-                    }
-                    _ = ticker.tick().fuse() => {
-                        outgoing.send(Ping(Vec::from(b"ping"))).await.unwrap();
-                    }
-                }
+/// Hold onto a peer that lost its connection so that they can resume, removing them if they do
+/// not come back in time.
+async fn suspend_peer(
+    state: &ServerState,
+    resume_token: ResumeToken,
+    suspended_peer: SuspendedPeer,
+) {
+    let peer_id = suspended_peer.peer_id.clone();
+
+    state
+        .suspended_peers
+        .lock()
+        .await
+        .insert(resume_token.clone(), suspended_peer);
+
+    tokio::spawn({
+        let state = state.clone();
+        async move {
+            tokio::time::sleep(state.options.resume_grace).await;
+            if state
+                .suspended_peers
+                .lock()
+                .await
+                .remove(&resume_token)
+                .is_some()
+            {
+                tracing::info!(%peer_id, "did not resume");
+                remove_peer(&state, &peer_id).await;
             }
+        }
+    });
+}
+
+/// Disconnect a peer for good, returning whether there was anyone to disconnect.
+async fn kick_peer(state: &ServerState, peer_id: &PeerId) -> bool {
+    let Some(kick) = state
+        .peers
+        .lock()
+        .await
+        .get(peer_id)
+        .map(|peer| peer.kick.clone())
+    else {
+        return false;
+    };
 
-            println!("{} {} disconnected", peer_id, &addr);
-            peers.lock().await.remove(&peer_id);
+    tracing::info!(target: AUDIT_TARGET, %peer_id, "peer kicked");
+    state
+        .metrics
+        .kicked
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
-            eyre::Ok(())
-        });
+    let suspended = {
+        let mut suspended_peers = state.suspended_peers.lock().await;
+        let len = suspended_peers.len();
+        suspended_peers.retain(|_, suspended_peer| &suspended_peer.peer_id != peer_id);
+        suspended_peers.len() != len
+    };
+
+    if suspended {
+        remove_peer(state, peer_id).await;
+    } else {
+        kick.notify_one();
     }
 
-    Ok(())
+    true
 }
 
-#[derive(Debug)]
-pub enum SignallingControl {
-    IceCandidate(PeerId, String),
-    Offer(PeerId, String),
-    Answer(PeerId, String),
-    RequestConnection(PeerId),
-    AcceptConnection(ConnectionId),
-    RejectConnection(ConnectionId),
-    _Pong(Vec<u8>),
-}
+/// Close every pending connection request that `peer_id` is part of.
+async fn close_connection_requests_for_peer(state: &ServerState, peer_id: &PeerId) {
+    let mut connection_requests = state.connection_requests.lock().await;
 
-#[derive(Debug)]
-pub enum SignallingEvent {
-    Id(PeerId),
-    ConectionRequest(PeerId, ConnectionId),
-    Offer(PeerId, String),
-    Answer(PeerId, String),
-    IceCandidate(PeerId, String),
-    ConnectionAccepted(PeerId, ConnectionId),
-    Error(SignallingError),
+    let connection_ids: Vec<ConnectionId> = connection_requests
+        .iter()
+        .filter(|(_, request)| &request.requester == peer_id || &request.requestee == peer_id)
+        .map(|(connection_id, _)| *connection_id)
+        .collect();
+
+    for connection_id in connection_ids {
+        if let Some(request) = connection_requests.remove(&connection_id) {
+            let _ = request.response.send(ConnectionResponse::Close(
+                RequestClosedReason::PeerDisconnected,
+            ));
+        }
+    }
 }
 
-async fn send_message(
-    write: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+/// Act on a message from a peer, returning the replies for them. Replies go straight out on the
+/// peer's websocket, not through their channel, which only their own connection empties.
+async fn handle_incoming_message_inner(
+    our_peer_id: PeerId,
+    state: ServerState,
     message: PeerToServerMessage,
-) -> Result<()> {
-    let string = serde_json::to_string(&message)?;
-    write.send(Message::text(string)).await?;
+) -> core::result::Result<Vec<ServerToPeerMessage>, SignallingError> {
+    tracing::debug!(
+        kind = message.inner.kind(),
+        job_id = message.job_id,
+        "incoming message"
+    );
+    tracing::trace!(?message);
+    let ServerState {
+        peers,
+        connection_requests,
+        ..
+    } = &state;
+    let job_id = message.job_id;
+    let reply = |inner| ServerToPeerMessage { job_id, inner };
+    match message.inner {
+        PeerToServer::Hello { .. } | PeerToServer::Authenticate { .. } => {
+            tracing::debug!("ignoring handshake from already authenticated peer");
+            Ok(vec![])
+        }
+        PeerToServer::ListPeers => {
+            let peer_infos = peers
+                .lock()
+                .await
+                .iter()
+                .filter(|(peer_id, _)| *peer_id != &our_peer_id)
+                .map(|(peer_id, peer)| PeerInfo {
+                    peer_id: peer_id.clone(),
+                    metadata: peer.metadata.clone(),
+                })
+                .collect();
+
+            Ok(vec![reply(ServerToPeer::Peers(peer_infos))])
+        }
+        PeerToServer::IceCandidate(peer_id, inner) => {
+            relay::check_candidate(&inner)?;
+            relay::relay(
+                &state,
+                &our_peer_id,
+                &peer_id,
+                ServerToPeer::IceCandidate(our_peer_id.clone(), inner),
+                Relayed::IceCandidate,
+            )
+            .await?;
+            Ok(vec![])
+        }
+        PeerToServer::Offer(peer_id, inner) => {
+            relay::check_description(&inner)?;
+            relay::relay(
+                &state,
+                &our_peer_id,
+                &peer_id,
+                ServerToPeer::Offer(our_peer_id.clone(), inner),
+                Relayed::Offer,
+            )
+            .await?;
+            Ok(vec![])
+        }
+        PeerToServer::Answer(peer_id, inner) => {
+            relay::check_description(&inner)?;
+            relay::relay(
+                &state,
+                &our_peer_id,
+                &peer_id,
+                ServerToPeer::Answer(our_peer_id.clone(), inner),
+                Relayed::Answer,
+            )
+            .await?;
+            Ok(vec![])
+        }
+        PeerToServer::ConnectToPeer(peer_id, invite) => {
+            let peer = peers.lock().await.get(&peer_id).map(|peer| peer.tx.clone());
+
+            if peer_id == our_peer_id {
+                Err(SignallingError::CannotConnectToSelf)
+            } else if let (Some(peer), Some(invite)) = (&peer, invite) {
+                let permission = invite::redeem(&state, &invite, &peer_id).await?;
+                let connection_id = make_connection_id();
+
+                relay::connect(&state, &our_peer_id, &peer_id, connection_id).await;
+
+                // NOTE(emily): The host hears first so that it is ready for the requester, who
+                // starts connecting as soon as they see that they were accepted.
+                if let Err(err) = try_send(
+                    &state,
+                    &peer_id,
+                    peer,
+                    ServerToPeer::InvitedConnection(our_peer_id.clone(), connection_id, permission),
+                ) {
+                    relay::disconnect(&state, &our_peer_id, &peer_id).await;
+                    return Err(err);
+                }
+
+                tracing::info!(
+                    target: AUDIT_TARGET,
+                    %connection_id,
+                    requester = %our_peer_id,
+                    requestee = %peer_id,
+                    ?permission,
+                    "connection accepted by invite"
+                );
+                state.metrics.relayed(Relayed::ConnectionRequest);
+                state.metrics.relayed(Relayed::ConnectionAccepted);
+
+                Ok(vec![
+                    reply(ServerToPeer::ConnectionRequested(
+                        peer_id.clone(),
+                        connection_id,
+                    )),
+                    ServerToPeerMessage {
+                        job_id: 0,
+                        inner: ServerToPeer::ConnectionAccepted(peer_id, connection_id),
+                    },
+                ])
+            } else if let Some(peer) = peer {
+                let connection_id =
+                    open_connection_request(&state, &our_peer_id, &peer_id, None).await?;
+
+                if let Err(err) = try_send(
+                    &state,
+                    &peer_id,
+                    &peer,
+                    ServerToPeer::ConnectionRequest(our_peer_id.clone(), connection_id),
+                ) {
+                    // NOTE(emily): Nobody is going to answer it.
+                    connection_requests.lock().await.remove(&connection_id);
+                    return Err(err);
+                }
+
+                tracing::info!(
+                    target: AUDIT_TARGET,
+                    %connection_id,
+                    requester = %our_peer_id,
+                    requestee = %peer_id,
+                    "connection requested"
+                );
+
+                Ok(vec![reply(ServerToPeer::ConnectionRequested(
+                    peer_id,
+                    connection_id,
+                ))])
+            } else {
+                Err(SignallingError::NoSuchPeer(peer_id))
+            }
+        }
+        PeerToServer::AcceptConnection(connection_id) => {
+            if let Some(connection_request) =
+                take_connection_request(connection_requests, &connection_id, |request| {
+                    request.requestee == our_peer_id
+                })
+                .await
+            {
+                connection_request
+                    .response
+                    .send(ConnectionResponse::Accept)
+                    .map_err(|_err| SignallingError::InternalError)?;
+            }
+            Ok(vec![])
+        }
+        PeerToServer::RejectConnection(connection_id) => {
+            if let Some(connection_request) =
+                take_connection_request(connection_requests, &connection_id, |request| {
+                    request.requestee == our_peer_id
+                })
+                .await
+            {
+                connection_request
+                    .response
+                    .send(ConnectionResponse::Reject)
+                    .map_err(|_err| SignallingError::InternalError)?;
+            }
+            Ok(vec![])
+        }
+        PeerToServer::CancelConnection(connection_id) => {
+            if let Some(connection_request) =
+                take_connection_request(connection_requests, &connection_id, |request| {
+                    request.requester == our_peer_id
+                })
+                .await
+            {
+                connection_request
+                    .response
+                    .send(ConnectionResponse::Close(RequestClosedReason::Cancelled))
+                    .map_err(|_err| SignallingError::InternalError)?;
+            }
+            Ok(vec![])
+        }
+        PeerToServer::CreateRoom(policy) => {
+            let room_id = room::create_room(&state, &our_peer_id, policy).await;
+
+            Ok(vec![reply(ServerToPeer::RoomCreated(room_id))])
+        }
+        PeerToServer::JoinRoom(room_id) => {
+            match room::check_join(&state, &room_id, &our_peer_id).await? {
+                room::Join::Admit => {
+                    let connection_id = make_connection_id();
+                    let joined = room::admit(&state, &room_id, &our_peer_id, connection_id).await?;
+                    Ok(joined.into_iter().map(reply).collect())
+                }
+                room::Join::AskHost(host) => {
+                    let connection_id =
+                        open_connection_request(&state, &our_peer_id, &host, Some(room_id.clone()))
+                            .await?;
+
+                    if let Err(err) = try_send_to_peer(
+                        &state,
+                        &host,
+                        ServerToPeer::RoomJoinRequest(
+                            room_id.clone(),
+                            our_peer_id.clone(),
+                            connection_id,
+                        ),
+                    )
+                    .await
+                    {
+                        connection_requests.lock().await.remove(&connection_id);
+                        return Err(err);
+                    }
+
+                    tracing::info!(
+                        target: AUDIT_TARGET,
+                        %connection_id,
+                        %room_id,
+                        requester = %our_peer_id,
+                        requestee = %host,
+                        "room join requested"
+                    );
+
+                    Ok(vec![reply(ServerToPeer::RoomJoinRequested(
+                        room_id,
+                        connection_id,
+                    ))])
+                }
+            }
+        }
+        PeerToServer::LeaveRoom(room_id) => {
+            room::leave(&state, &room_id, &our_peer_id).await?;
+            Ok(vec![])
+        }
+        PeerToServer::InviteToRoom(room_id, peer_id) => {
+            room::invite(&state, &room_id, &our_peer_id, &peer_id).await?;
+            Ok(vec![])
+        }
+        PeerToServer::SetRoomPolicy(room_id, policy) => {
+            room::set_policy(&state, &room_id, &our_peer_id, policy).await?;
+            Ok(vec![])
+        }
+        PeerToServer::CreateInvite(options) => {
+            let (invite, ttl) = invite::create_invite(&state, &our_peer_id, options).await;
+
+            Ok(vec![reply(ServerToPeer::InviteCreated(invite, ttl))])
+        }
+        PeerToServer::RevokeInvite(invite) => {
+            invite::revoke(&state, &invite, &our_peer_id).await?;
+            Ok(vec![])
+        }
+    }
+}
+
+async fn handle_incoming_text_message(
+    our_peer_id: PeerId,
+    state: ServerState,
+    rate_limiter: &mut RateLimiter,
+    msg: String,
+) -> core::result::Result<Vec<ServerToPeerMessage>, Option<ServerToPeerMessage>> {
+    if msg.len() > state.options.max_message_size {
+        return Err(Some(ServerToPeerMessage {
+            job_id: 0,
+            inner: ServerToPeer::Error(SignallingError::MessageTooLarge),
+        }));
+    }
+
+    let message = serde_json::from_str::<PeerToServerMessage>(&msg).map_err(|err| {
+        tracing::debug!(%our_peer_id, "malformed message {err}");
+        ServerToPeerMessage {
+            job_id: 0,
+            inner: ServerToPeer::Error(SignallingError::MalformedMessage),
+        }
+    })?;
+    let job_id = message.job_id;
+
+    if !rate_limiter.allow(&message.inner) {
+        return Err(Some(ServerToPeerMessage {
+            job_id,
+            inner: ServerToPeer::Error(SignallingError::RateLimited),
+        }));
+    }
+
+    Ok(handle_incoming_message_inner(our_peer_id, state, message)
+        .await
+        .map_err(|err| ServerToPeerMessage {
+            job_id: job_id,
+            inner: ServerToPeer::Error(err),
+        })?)
+}
+
+async fn handle_incoming_message(
+    our_peer_id: PeerId,
+    outgoing: &mut SplitSink<WebSocketStream<PeerStream>, Message>,
+    state: ServerState,
+    rate_limiter: &mut RateLimiter,
+    msg: Message,
+) -> core::result::Result<Vec<ServerToPeerMessage>, Option<ServerToPeerMessage>> {
+    match msg {
+        Text(text_message) => {
+            handle_incoming_text_message(our_peer_id, state, rate_limiter, text_message).await
+        }
+        Binary(_) | Frame(_) => Err(Some(ServerToPeerMessage {
+            job_id: 0,
+            inner: ServerToPeer::Error(SignallingError::UnsupportedFrame),
+        })),
+        Close(_) => {
+            tracing::debug!(peer_id = %our_peer_id, "close");
+            Err(None)
+        }
+        Ping(data) => {
+            // NOTE(emily): If this fails then so will the next read, which deals with it.
+            if let Err(err) = outgoing
+                .send(tokio_tungstenite::tungstenite::Message::Pong(data))
+                .await
+            {
+                tracing::debug!(%our_peer_id, "unable to pong {err}");
+            }
+            Ok(vec![])
+        }
+        Pong(_data) => Ok(vec![]),
+    }
+}
+
+async fn handle_outgoing(
+    outgoing: &mut SplitSink<WebSocketStream<PeerStream>, Message>,
+    msg: ServerToPeerMessage,
+) -> Result<()> {
+    let text = serde_json::to_string(&msg)?;
+    let message = tokio_tungstenite::tungstenite::Message::text(text);
+    outgoing.send(message).await?;
+
     eyre::Ok(())
 }
 
-async fn handle_control(
-    write: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-    control: SignallingControl,
+async fn handle_outgoing_all(
+    outgoing: &mut SplitSink<WebSocketStream<PeerStream>, Message>,
+    msgs: Vec<ServerToPeerMessage>,
 ) -> Result<()> {
-    tracing::debug!("sending to server {control:?}");
+    for msg in msgs {
+        handle_outgoing(outgoing, msg).await?;
+    }
 
-    let message = match control {
-        SignallingControl::IceCandidate(peer_id, candidate) => PeerToServerMessage {
-            job_id: 0,
-            inner: PeerToServer::IceCandidate(peer_id, candidate),
+    eyre::Ok(())
+}
+
+/// Wait for a newly connected peer to say hello, and work out which features we both know about.
+async fn hello_peer(
+    outgoing: &mut SplitSink<WebSocketStream<PeerStream>, Message>,
+    incoming: &mut SplitStream<WebSocketStream<PeerStream>>,
+    options: &ServerOptions,
+) -> core::result::Result<Features, SignallingError> {
+    let msg = tokio::time::timeout(AUTH_TIMEOUT, incoming.next())
+        .await
+        .map_err(|_| SignallingError::MalformedMessage)?;
+
+    let (protocol_version, features) = match msg {
+        Some(Ok(Text(text))) => match serde_json::from_str::<PeerToServerMessage>(&text) {
+            Ok(PeerToServerMessage {
+                inner:
+                    PeerToServer::Hello {
+                        protocol_version,
+                        features,
+                    },
+                ..
+            }) => (protocol_version, features),
+            _ => return Err(SignallingError::MalformedMessage),
         },
-        SignallingControl::Offer(peer_id, offer) => PeerToServerMessage {
+        _ => return Err(SignallingError::MalformedMessage),
+    };
+
+    if protocol_version != PROTOCOL_VERSION {
+        return Err(SignallingError::IncompatibleVersion(PROTOCOL_VERSION));
+    }
+
+    let features: Features = features
+        .into_iter()
+        .filter(|feature| features::ALL.contains(&feature.as_str()))
+        .filter(|feature| feature != features::RESUME || !options.resume_grace.is_zero())
+        .filter(|feature| feature != features::ICE_SERVERS || options.turn.is_some())
+        .collect();
+
+    handle_outgoing(
+        outgoing,
+        ServerToPeerMessage {
             job_id: 0,
-            inner: PeerToServer::Offer(peer_id, offer),
+            inner: ServerToPeer::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                features: features.iter().cloned().collect(),
+            },
         },
-        SignallingControl::Answer(peer_id, answer) => PeerToServerMessage {
+    )
+    .await
+    .map_err(|_err| SignallingError::InternalError)?;
+
+    Ok(features)
+}
+
+/// Challenge a newly connected peer and wait for them to authenticate.
+async fn authenticate_peer(
+    outgoing: &mut SplitSink<WebSocketStream<PeerStream>, Message>,
+    incoming: &mut SplitStream<WebSocketStream<PeerStream>>,
+    auth: &AuthOptions,
+) -> Result<(
+    auth::Identity,
+    Option<PeerId>,
+    PeerMetadata,
+    Option<ResumeToken>,
+)> {
+    let challenge = auth::make_challenge();
+
+    handle_outgoing(
+        outgoing,
+        ServerToPeerMessage {
             job_id: 0,
-            inner: PeerToServer::Answer(peer_id, answer),
+            inner: ServerToPeer::AuthChallenge(challenge.clone()),
         },
-        SignallingControl::RequestConnection(peer_id) => PeerToServerMessage {
-            job_id: 0,
-            inner: PeerToServer::ConnectToPeer(peer_id),
+    )
+    .await?;
+
+    let msg = tokio::time::timeout(AUTH_TIMEOUT, incoming.next())
+        .await
+        .map_err(|_| eyre!("timed out waiting for authentication"))?;
+
+    match msg {
+        Some(Ok(Text(text))) => match serde_json::from_str::<PeerToServerMessage>(&text)?.inner {
+            PeerToServer::Authenticate {
+                response,
+                peer_id,
+                metadata,
+                resume_token,
+            } => auth
+                .authenticate(&challenge, &response)
+                .map(|identity| (identity, peer_id, metadata, resume_token))
+                .ok_or(eyre!("bad credentials {response:?}")),
+            other => Err(eyre!("expected authentication, got {other:?}")),
         },
-        SignallingControl::AcceptConnection(connection_id) => PeerToServerMessage {
+        other => Err(eyre!("expected authentication, got {other:?}")),
+    }
+}
+
+/// Tell a peer that we will not talk to them and close their websocket.
+async fn refuse_peer(
+    outgoing: &mut SplitSink<WebSocketStream<PeerStream>, Message>,
+    error: SignallingError,
+) {
+    let reason = error.to_string();
+
+    let _ = handle_outgoing(
+        outgoing,
+        ServerToPeerMessage {
             job_id: 0,
-            inner: PeerToServer::AcceptConnection(connection_id),
+            inner: ServerToPeer::Error(error),
         },
-        SignallingControl::RejectConnection(_connection_id) => todo!(),
-        SignallingControl::_Pong(data) => {
-            write.send(Message::Pong(data)).await?;
-            return Ok(());
-        }
+    )
+    .await;
+    let _ = outgoing
+        .send(Close(Some(CloseFrame {
+            code: CloseCode::Policy,
+            reason: reason.into(),
+        })))
+        .await;
+}
+
+/// How long peers have to hear that the server is shutting down before it stops anyway.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Run the signal server, listening on each of `addresses` (and for admin requests if enabled).
+pub async fn server<A: AsRef<str>>(addresses: &[A], options: ServerOptions) -> Result<()> {
+    server_with_shutdown(addresses, options, std::future::pending()).await
+}
+
+/// Like [`server`], but once `shutdown` completes stop accepting connections, tell every peer
+/// that the server is going away and close their connections.
+pub async fn server_with_shutdown<A: AsRef<str>>(
+    addresses: &[A],
+    options: ServerOptions,
+    shutdown: impl std::future::Future<Output = ()> + Send,
+) -> Result<()> {
+    let mut listeners = vec![];
+    for address in addresses {
+        let address = address.as_ref();
+        listeners.push(
+            tokio::net::TcpListener::bind(address)
+                .await
+                .map_err(|err| eyre!("unable to listen on {address}: {err}"))?,
+        );
+    }
+
+    let admin_listener = match &options.admin {
+        Some(admin) => Some(
+            tokio::net::TcpListener::bind(&admin.address)
+                .await
+                .map_err(|err| eyre!("unable to listen on {}: {err}", admin.address))?,
+        ),
+        None => None,
     };
 
-    Ok(send_message(write, message).await?)
+    serve(listeners, admin_listener, options, shutdown).await
 }
 
-async fn handle_message(
-    control_tx: mpsc::Sender<SignallingControl>,
-    event_tx: mpsc::Sender<SignallingEvent>,
-    msg: Message,
+async fn serve(
+    listeners: Vec<tokio::net::TcpListener>,
+    admin_listener: Option<tokio::net::TcpListener>,
+    options: ServerOptions,
+    shutdown: impl std::future::Future<Output = ()> + Send,
 ) -> Result<()> {
-    match msg {
-        Text(text) => {
-            let message = serde_json::from_str::<ServerToPeerMessage>(&text)?;
-            tracing::debug!("received {message:?}");
-            let _job_id = message.job_id;
-            Ok(event_tx
-                .send(match message.inner {
-                    ServerToPeer::Id(peer_id) => SignallingEvent::Id(peer_id),
-                    ServerToPeer::ConnectionRequest(peer_id, connection_id) => {
-                        SignallingEvent::ConectionRequest(peer_id, connection_id)
-                    }
-                    ServerToPeer::ConnectionAccepted(peer_id, connection_id) => {
-                        SignallingEvent::ConnectionAccepted(peer_id, connection_id)
-                    }
-                    ServerToPeer::Offer(peer_id, offer) => SignallingEvent::Offer(peer_id, offer),
-                    ServerToPeer::Answer(peer_id, answer) => {
-                        SignallingEvent::Answer(peer_id, answer)
-                    }
-                    ServerToPeer::IceCandidate(peer_id, ice_candidate) => {
-                        SignallingEvent::IceCandidate(peer_id, ice_candidate)
-                    }
-                    ServerToPeer::Error(error) => SignallingEvent::Error(error),
+    let acceptor = match &options.tls {
+        Some(tls) => Some(tls::acceptor(tls.clone()).await?),
+        None => None,
+    };
+
+    // NOTE(emily): Without a token anyone who can reach the admin listener can see who is online,
+    // so only allow that when it is just us.
+    if let (Some(admin_listener), Some(admin)) = (&admin_listener, &options.admin) {
+        let address = admin_listener.local_addr()?;
+        if admin.token.is_none() && !address.ip().is_loopback() {
+            return Err(eyre!(
+                "the admin listener on {address} needs a token when it is not on a loopback address"
+            ));
+        }
+    }
+
+    let state = ServerState::new(options);
+
+    if let Some(admin_listener) = admin_listener {
+        let router = admin::router(state.clone());
+        let mut shutdown = state.shutdown.subscribe();
+        tokio::spawn(async move {
+            if let Err(err) = axum::serve(admin_listener, router)
+                .with_graceful_shutdown(async move {
+                    let _ = shutdown.wait_for(|shutting_down| *shutting_down).await;
                 })
-                .await?)
+                .await
+            {
+                tracing::error!(%err, "admin listener failed");
+            }
+        });
+    }
+
+    // NOTE(emily): Every connection holds onto a clone of this, so once they are all gone recv
+    // gives back None.
+    let (drain_tx, mut drain_rx) = mpsc::channel::<()>(1);
+
+    let accept =
+        futures::future::try_join_all(listeners.into_iter().map(|listener| {
+            accept_peers(listener, acceptor.clone(), state.clone(), drain_tx.clone())
+        }));
+
+    // NOTE(emily): Dropping accept drops the listeners too.
+    futures::select! {
+        result = accept.fuse() => {
+            result?;
         }
-        Binary(_) | Frame(_) => Err(eyre!("No idea what to do with binary")),
-        Close(_) => Err(eyre!("Going down")),
-        Ping(data) => Ok(control_tx.send(SignallingControl::_Pong(data)).await?),
-        Pong(_) => Ok(()),
+        _ = shutdown.fuse() => {}
     }
-}
 
-#[tracing::instrument]
-pub async fn client(
-    address: &str,
-) -> Result<(
-    mpsc::Sender<SignallingControl>,
-    mpsc::Receiver<SignallingEvent>,
-)> {
-    tracing::info!("starting signal client");
-    let (ws_stream, _) = tokio_tungstenite::connect_async(address)
-        .await
-        .expect("Error during the websocket handshake occurred");
+    let peers = state.peers.lock().await.len();
+    tracing::info!(peers, "shutting down");
+    state.shutdown.send_replace(true);
 
-    let (mut write, mut read) = ws_stream.split();
+    drop(drain_tx);
+    if tokio::time::timeout(SHUTDOWN_GRACE, drain_rx.recv())
+        .await
+        .is_err()
+    {
+        tracing::warn!("gave up waiting for connections to close");
+    }
 
-    let (control_tx, mut control_rx) =
-        mpsc::channel::<SignallingControl>(ARBITRARY_SIGNALLING_CHANNEL_LIMIT);
-    let (event_tx, event_rx) = mpsc::channel::<SignallingEvent>(ARBITRARY_SIGNALLING_CHANNEL_LIMIT);
+    Ok(())
+}
 
-    tracing::info!("client signal connected");
+async fn accept_peers(
+    listener: tokio::net::TcpListener,
+    acceptor: Option<tokio_rustls::TlsAcceptor>,
+    state: ServerState,
+    drain_tx: mpsc::Sender<()>,
+) -> Result<()> {
+    while let Ok((conn, addr)) = listener.accept().await {
+        let state = state.clone();
+        let acceptor = acceptor.clone();
+        let drain_tx = drain_tx.clone();
+        let span = tracing::info_span!("peer", %addr, peer_id = tracing::field::Empty);
+        tokio::spawn(
+            async move {
+                let _drain_tx = drain_tx;
+                tracing::debug!("incoming connection");
 
-    tokio::spawn({
-        let control_tx = control_tx.downgrade();
-        async move {
-            loop {
-                match futures::select! {
-                    control = control_rx.recv().fuse() => {
-                        match control {
-                            None => {
-                                tracing::warn!("control_rx None");
-                                break;
-                            },
-                            Some(control) => handle_control(&mut write, control).await,
-                        }
-                    }
-                    msg = read.next().fuse() => {
-                        if let Some(control_tx) = control_tx.upgrade() {
-                            match msg {
-                                Some(Ok(msg)) => handle_message(control_tx.clone(), event_tx.clone(), msg).await,
-                                None => {
-                                    tracing::warn!("error reading from websocket (None)");
-                                    break
-                                },
-                                Some(Err(err)) => {
-                                    tracing::warn!("error reading from websocket ({err})");
-                                    break
-                                },
-                            }
-                        } else {
-                            break;
+                let conn = match acceptor {
+                    Some(acceptor) => match acceptor.accept(conn).await {
+                        Ok(conn) => PeerStream::Tls(Box::new(conn)),
+                        Err(err) => {
+                            tracing::info!(%err, "tls handshake failed");
+                            return eyre::Ok(());
                         }
-                    }
-                } {
-                    Ok(_ok) => {}
-                    Err(err) => {
-                        tracing::error!("err {err}");
-                        break;
-                    }
-                }
+                    },
+                    None => PeerStream::Plain(conn),
+                };
+
+                handle_peer(conn, addr, state).await
             }
+            .instrument(span),
+        );
+    }
 
-            tracing::info!("signal client done");
+    Ok(())
+}
+
+/// Run a peer's connection to the server, from the websocket handshake until they go away.
+async fn handle_peer(conn: PeerStream, addr: SocketAddr, state: ServerState) -> Result<()> {
+    let ws_stream = tokio_tungstenite::accept_async(conn).await?;
+    state
+        .metrics
+        .connections
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+    let (mut outgoing, mut incoming) = ws_stream.split();
+
+    let features = match hello_peer(&mut outgoing, &mut incoming, &state.options).await {
+        Ok(features) => features,
+        Err(err) => {
+            tracing::info!(%err, "hello failed");
+            refuse_peer(&mut outgoing, err).await;
+            return Ok(());
         }
-    });
+    };
 
-    Ok((control_tx, event_rx))
+    let (identity, requested_id, metadata, resume_token) =
+        match authenticate_peer(&mut outgoing, &mut incoming, &state.options.auth).await {
+            Ok(authenticated) => authenticated,
+            Err(err) => {
+                tracing::info!(target: AUDIT_TARGET, %addr, %err, "authentication failed");
+                refuse_peer(&mut outgoing, SignallingError::AuthenticationFailed).await;
+                return Ok(());
+            }
+        };
+
+    let resumable = features.contains(features::RESUME);
+
+    let suspended_peer = match &resume_token {
+        Some(resume_token) if resumable => state.suspended_peers.lock().await.remove(resume_token),
+        _ => None,
+    };
+
+    let resumed = suspended_peer.is_some();
+
+    let (peer_id, tx, mut rx, kick) = match suspended_peer {
+        Some(SuspendedPeer { peer_id, tx, rx }) => {
+            let kick = state.peers.lock().await.get_mut(&peer_id).map(|peer| {
+                peer.address = addr;
+                peer.kick.clone()
+            });
+
+            match kick {
+                Some(kick) => (peer_id, tx, rx, kick),
+                None => {
+                    // NOTE(emily): Removed whilst we were resuming them.
+                    refuse_peer(&mut outgoing, SignallingError::Kicked).await;
+                    return Ok(());
+                }
+            }
+        }
+        None => {
+            let (tx, rx) = mpsc::channel(state.options.channel_limit);
+            let kick = Arc::new(tokio::sync::Notify::new());
+
+            let peer = Peer {
+                tx: tx.clone(),
+                metadata: metadata.clone(),
+                address: addr,
+                connected_at: std::time::SystemTime::now(),
+                kick: kick.clone(),
+            };
+
+            match register_peer(&state, &identity, requested_id, peer).await {
+                Ok(peer_id) => (peer_id, tx, rx, kick),
+                Err(err) => {
+                    tracing::info!(%err, "id refused");
+                    refuse_peer(&mut outgoing, err).await;
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    tracing::Span::current().record("peer_id", tracing::field::display(&peer_id));
+    tracing::info!(
+        target: AUDIT_TARGET,
+        %peer_id,
+        %addr,
+        %identity,
+        resumed,
+        "peer connected"
+    );
+
+    // NOTE(emily): A new token every time so that an old session's expiry can never be
+    // mistaken for this one.
+    let resume_token = auth::make_resume_token();
+
+    // NOTE(emily): Send the id straight away rather than through tx, anything that queued
+    // up whilst this peer was suspended needs to come after it.
+    if let Err(err) = handle_outgoing(
+        &mut outgoing,
+        ServerToPeerMessage {
+            job_id: 0,
+            inner: ServerToPeer::Id {
+                peer_id: peer_id.clone(),
+                resume_token: resume_token.clone(),
+                resumed,
+            },
+        },
+    )
+    .await
+    {
+        tracing::info!(%err, "unable to send id");
+        if resumable {
+            suspend_peer(&state, resume_token, SuspendedPeer { peer_id, tx, rx }).await;
+        } else {
+            remove_peer(&state, &peer_id).await;
+        }
+        return Ok(());
+    }
+
+    if !resumed {
+        broadcast(
+            &state,
+            &peer_id,
+            ServerToPeer::PeerJoined(PeerInfo {
+                peer_id: peer_id.clone(),
+                metadata,
+            }),
+        )
+        .await;
+    }
+
+    // NOTE(emily): Peers that close their websocket on purpose are not coming back.
+    let mut closed = false;
+    let mut malformed_messages = 0;
+    let mut rate_limiter = RateLimiter::new(&state.options.rate_limits);
+    let mut rate_limited_messages = 0;
+
+    let mut ticker = tokio::time::interval(state.options.ping_interval);
+    let mut last_heard = tokio::time::Instant::now();
+
+    let mut shutdown = state.shutdown.subscribe();
+
+    let turn = state
+        .options
+        .turn
+        .as_ref()
+        .filter(|_| features.contains(features::ICE_SERVERS));
+    // NOTE(emily): Fires straight away, then hands out new credentials well before the
+    // old ones run out.
+    let mut turn_ticker = tokio::time::interval(
+        turn.map_or(Duration::from_secs(60 * 60), |turn| turn.ttl / 2)
+            .max(Duration::from_secs(1)),
+    );
+
+    loop {
+        futures::select! {
+            msg = incoming.next().fuse() => {
+                match msg {
+                    Some(Ok(msg)) => {
+                        last_heard = tokio::time::Instant::now();
+                        let replies = match handle_incoming_message(peer_id.clone(), &mut outgoing, state.clone(), &mut rate_limiter, msg).await {
+                            Ok(replies) => replies,
+                            Err(Some(ServerToPeerMessage {
+                                inner: ServerToPeer::Error(SignallingError::RateLimited),
+                                job_id,
+                            })) => {
+                                rate_limited_messages += 1;
+                                state.metrics.rate_limited_messages.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                if state
+                                    .options
+                                    .rate_limits
+                                    .disconnect_after
+                                    .is_some_and(|limit| rate_limited_messages >= limit)
+                                {
+                                    tracing::info!("disconnecting, rate limited too often");
+                                    refuse_peer(&mut outgoing, SignallingError::RateLimited).await;
+                                    closed = true;
+                                    break;
+                                }
+
+                                vec![ServerToPeerMessage {
+                                    job_id,
+                                    inner: ServerToPeer::Error(SignallingError::RateLimited),
+                                }]
+                            }
+                            Err(Some(ServerToPeerMessage {
+                                inner: ServerToPeer::Error(
+                                    err @ (SignallingError::MalformedMessage
+                                    | SignallingError::UnsupportedFrame
+                                    | SignallingError::MessageTooLarge),
+                                ),
+                                job_id,
+                            })) => {
+                                malformed_messages += 1;
+                                state.metrics.malformed_messages.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                if state
+                                    .options
+                                    .malformed_message_limit
+                                    .is_some_and(|limit| malformed_messages >= limit)
+                                {
+                                    tracing::info!("disconnecting, too many malformed messages");
+                                    refuse_peer(&mut outgoing, err).await;
+                                    closed = true;
+                                    break;
+                                }
+
+                                vec![ServerToPeerMessage {
+                                    job_id,
+                                    inner: ServerToPeer::Error(err),
+                                }]
+                            }
+                            Err(Some(response)) => vec![response],
+                            Err(None) => {
+                                tracing::info!("closed");
+                                closed = true;
+                                break;
+                            }
+                        };
+
+                        // NOTE(emily): Not through tx, this loop is the only thing that empties it
+                        // so waiting for room in it here could wait forever.
+                        if let Err(err) = handle_outgoing_all(&mut outgoing, replies).await {
+                            tracing::info!(%err, "unable to reply");
+                            break;
+                        }
+                    }
+                    None => {
+                        tracing::info!("websocket ended");
+                        break;
+                    }
+                    Some(Err(err)) => {
+                        tracing::info!(%err, "websocket failed");
+                        break;
+                    },
+                }
+            },
+            msg = rx.recv().fuse() => {
+                match msg {
+                    Some(msg) => {
+                        if let Err(err) = handle_outgoing(&mut outgoing, msg).await {
+                            tracing::info!(%err, "unable to send");
+                            break;
+                        }
+                    }
+                    None => {
+                        tracing::info!("outgoing channel closed");
+                        break;
+                    },
+                }
+            }
+            _ = ticker.tick().fuse() => {
+                if last_heard.elapsed() > state.options.idle_timeout {
+                    tracing::info!("idle");
+                    break;
+                }
+
+                if let Err(err) = outgoing.send(Ping(Vec::from(b"ping"))).await {
+                    tracing::info!(%err, "unable to ping");
+                    break;
+                }
+            }
+            _ = kick.notified().fuse() => {
+                tracing::info!("kicked");
+                refuse_peer(&mut outgoing, SignallingError::Kicked).await;
+                closed = true;
+                break;
+            }
+            _ = shutdown.wait_for(|shutting_down| *shutting_down).map(|_| ()).fuse() => {
+                tracing::info!("server shutting down");
+                if features.contains(features::SHUTDOWN_NOTICE) {
+                    let message = ServerToPeerMessage {
+                        job_id: 0,
+                        inner: ServerToPeer::ServerShuttingDown {
+                            retry_after: state.options.shutdown_retry_after,
+                        },
+                    };
+                    let _ = handle_outgoing(&mut outgoing, message).await;
+                }
+                let _ = outgoing
+                    .send(Close(Some(CloseFrame {
+                        code: CloseCode::Away,
+                        reason: "server shutting down".into(),
+                    })))
+                    .await;
+                // NOTE(emily): Wait for them to close their end too, dropping the connection with
+                // anything still unread in it resets it and they might never see the close.
+                let _ = tokio::time::timeout(Duration::from_secs(1), async {
+                    while let Some(Ok(_)) = incoming.next().await {}
+                })
+                .await;
+                closed = true;
+                break;
+            }
+            _ = turn_ticker.tick().fuse() => {
+                if let Some(turn) = turn {
+                    let message = ServerToPeerMessage {
+                        job_id: 0,
+                        inner: ServerToPeer::IceServers(vec![turn.ice_server(&peer_id)]),
+                    };
+                    if let Err(err) = handle_outgoing(&mut outgoing, message).await {
+                        tracing::info!(%err, "unable to send ice servers");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    tracing::info!(closed, resumable, "disconnected");
+    if closed || !resumable {
+        remove_peer(&state, &peer_id).await;
+    } else {
+        suspend_peer(&state, resume_token, SuspendedPeer { peer_id, tx, rx }).await;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum SignallingControl {
+    IceCandidate(PeerId, String),
+    Offer(PeerId, String),
+    Answer(PeerId, String),
+    RequestConnection(PeerId),
+    AcceptConnection(ConnectionId),
+    RejectConnection(ConnectionId),
+    CancelConnection(ConnectionId),
+    /// Ask the server for everyone who is online, answered with [`SignallingEvent::Peers`].
+    ListPeers,
+    LeaveRoom(RoomId),
+    InviteToRoom(RoomId, PeerId),
+    SetRoomPolicy(RoomId, JoinPolicy),
+    RevokeInvite(InviteToken),
+    /// Send a request whose response goes to `response` instead of the event stream, see
+    /// [`request`].
+    Request(
+        SignallingRequest,
+        oneshot::Sender<core::result::Result<SignallingResponse, SignallingError>>,
+    ),
+    _Pong(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum SignallingRequest {
+    RequestConnection(PeerId, Option<InviteToken>),
+    ListPeers,
+    CreateRoom(JoinPolicy),
+    JoinRoom(RoomId),
+    CreateInvite(InviteOptions),
+}
+
+#[derive(Debug)]
+pub enum SignallingResponse {
+    ConnectionRequested(PeerId, ConnectionId),
+    Peers(Vec<PeerInfo>),
+    RoomCreated(RoomId),
+    /// In the room, with this host.
+    RoomJoined(RoomId, PeerId),
+    /// Waiting on the host to answer this connection request.
+    RoomJoinRequested(RoomId, ConnectionId),
+    InviteCreated(InviteToken, Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Lost the connection to the server, about to make reconnection attempt `attempt`.
+    Reconnecting { attempt: u32 },
+    /// The server is going away, we will try to reconnect after `retry_after`.
+    ServerShuttingDown { retry_after: Duration },
+    /// Connected to the server again. Unless `resumed` is set the server had already forgotten
+    /// about us, so any connection requests that we had pending are gone.
+    Reconnected { resumed: bool },
+    /// Gave up on the server, there will be no more events.
+    Disconnected,
+}
+
+#[derive(Debug)]
+pub enum SignallingEvent {
+    Id(PeerId),
+    ConnectionState(ConnectionState),
+    ConectionRequest(PeerId, ConnectionId),
+    /// A connection request that we made is now pending with this id.
+    ConnectionRequested(PeerId, ConnectionId),
+    ConnectionRequestClosed(PeerId, ConnectionId, RequestClosedReason),
+    Offer(PeerId, String),
+    Answer(PeerId, String),
+    IceCandidate(PeerId, String),
+    ConnectionAccepted(PeerId, ConnectionId),
+    ConnectionRejected(PeerId, ConnectionId, RejectionReason),
+    Peers(Vec<PeerInfo>),
+    PeerJoined(PeerInfo),
+    PeerLeft(PeerId),
+    /// ICE servers to use for new connections, replacing any that came before.
+    IceServers(Vec<IceServer>),
+    RoomCreated(RoomId),
+    RoomJoinRequested(RoomId, ConnectionId),
+    /// A peer wants to join a room that we host, answer it like a connection request.
+    RoomJoinRequest(RoomId, PeerId, ConnectionId),
+    /// We are in the room, connect to its host. This comes as an event even if we asked to join
+    /// with [`join_room`].
+    RoomJoined(RoomId, PeerId),
+    /// Someone was let into a room that we are in, if we are the host they will connect to us.
+    RoomMemberJoined(RoomId, PeerInfo),
+    RoomMemberLeft(RoomId, PeerId),
+    RoomInvitation(RoomId, PeerId),
+    RoomClosed(RoomId),
+    InviteCreated(InviteToken, Duration),
+    /// A peer connected using one of our invites, which accepted the connection for us.
+    InvitedConnection(PeerId, ConnectionId, Permission),
+    Error(SignallingError),
+}
+
+type ClientWebSocket = WebSocketStream<MaybeTlsStream<ClientStream>>;
+
+async fn send_message(
+    write: &mut SplitSink<ClientWebSocket, Message>,
+    message: PeerToServerMessage,
+) -> Result<()> {
+    let string = serde_json::to_string(&message)?;
+    write.send(Message::text(string)).await?;
+    eyre::Ok(())
+}
+
+type ResponseSender = oneshot::Sender<core::result::Result<SignallingResponse, SignallingError>>;
+
+/// Requests that are waiting on a response from the server, by job id.
+#[derive(Default)]
+struct PendingRequests {
+    next_job_id: usize,
+    requests: HashMap<usize, ResponseSender>,
+}
+
+impl PendingRequests {
+    fn insert(&mut self, response: ResponseSender) -> usize {
+        // NOTE(emily): Whoever made the request gives up on it after REQUEST_TIMEOUT, get rid of
+        // any that the server never answered.
+        self.requests.retain(|_, response| !response.is_closed());
+
+        // NOTE(emily): 0 is for messages that are not a response to anything.
+        self.next_job_id = self.next_job_id.wrapping_add(1).max(1);
+        self.requests.insert(self.next_job_id, response);
+        self.next_job_id
+    }
+}
+
+async fn handle_control(
+    write: &mut SplitSink<ClientWebSocket, Message>,
+    pending: &mut PendingRequests,
+    control: SignallingControl,
+) -> Result<()> {
+    tracing::debug!("sending to server {control:?}");
+
+    let message = match control {
+        SignallingControl::IceCandidate(peer_id, candidate) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::IceCandidate(peer_id, candidate),
+        },
+        SignallingControl::Offer(peer_id, offer) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::Offer(peer_id, offer),
+        },
+        SignallingControl::Answer(peer_id, answer) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::Answer(peer_id, answer),
+        },
+        SignallingControl::RequestConnection(peer_id) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::ConnectToPeer(peer_id, None),
+        },
+        SignallingControl::AcceptConnection(connection_id) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::AcceptConnection(connection_id),
+        },
+        SignallingControl::RejectConnection(connection_id) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::RejectConnection(connection_id),
+        },
+        SignallingControl::CancelConnection(connection_id) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::CancelConnection(connection_id),
+        },
+        SignallingControl::ListPeers => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::ListPeers,
+        },
+        SignallingControl::LeaveRoom(room_id) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::LeaveRoom(room_id),
+        },
+        SignallingControl::InviteToRoom(room_id, peer_id) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::InviteToRoom(room_id, peer_id),
+        },
+        SignallingControl::SetRoomPolicy(room_id, policy) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::SetRoomPolicy(room_id, policy),
+        },
+        SignallingControl::RevokeInvite(invite) => PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::RevokeInvite(invite),
+        },
+        SignallingControl::Request(request, response) => PeerToServerMessage {
+            job_id: pending.insert(response),
+            inner: match request {
+                SignallingRequest::RequestConnection(peer_id, invite) => {
+                    PeerToServer::ConnectToPeer(peer_id, invite)
+                }
+                SignallingRequest::ListPeers => PeerToServer::ListPeers,
+                SignallingRequest::CreateRoom(policy) => PeerToServer::CreateRoom(policy),
+                SignallingRequest::JoinRoom(room_id) => PeerToServer::JoinRoom(room_id),
+                SignallingRequest::CreateInvite(options) => PeerToServer::CreateInvite(options),
+            },
+        },
+        SignallingControl::_Pong(data) => {
+            write.send(Message::Pong(data)).await?;
+            return Ok(());
+        }
+    };
+
+    Ok(send_message(write, message).await?)
+}
+
+/// Read the next text message from the server, skipping over any other frames.
+async fn next_server_message(
+    read: &mut SplitStream<ClientWebSocket>,
+) -> Result<ServerToPeerMessage> {
+    loop {
+        match read.next().await {
+            Some(Ok(Text(text))) => return Ok(serde_json::from_str(&text)?),
+            Some(Ok(Ping(_) | Pong(_))) => {}
+            Some(Ok(Close(frame))) => return Err(eyre!("server closed connection {frame:?}")),
+            Some(Ok(other)) => return Err(eyre!("unexpected message from server {other:?}")),
+            Some(Err(err)) => return Err(err.into()),
+            None => return Err(eyre!("server closed connection")),
+        }
+    }
+}
+
+/// How the client retries when it loses its connection to the server.
+#[derive(Debug, Clone)]
+pub struct ReconnectOptions {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up after this many attempts, if unset keep trying forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ClientOptions {
+    pub credentials: Credentials,
+    /// Id to ask the server for, if unset the server picks a random one.
+    pub peer_id: Option<PeerId>,
+    pub metadata: PeerMetadata,
+    pub reconnect: ReconnectOptions,
+    /// Certificates to trust for `wss://` servers on top of the usual web roots, e.g. a
+    /// self-signed one (as DER).
+    pub tls_roots: Vec<CertificateDer<'static>>,
+}
+
+/// An authenticated connection to the server.
+struct Session {
+    write: SplitSink<ClientWebSocket, Message>,
+    read: SplitStream<ClientWebSocket>,
+    peer_id: PeerId,
+    resume_token: ResumeToken,
+    resumed: bool,
+    features: Features,
+}
+
+/// Tell the server which version we speak and find out which features we can use.
+async fn hello(
+    write: &mut SplitSink<ClientWebSocket, Message>,
+    read: &mut SplitStream<ClientWebSocket>,
+) -> Result<Features> {
+    send_message(
+        write,
+        PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                features: features::ALL
+                    .iter()
+                    .map(|feature| feature.to_string())
+                    .collect(),
+            },
+        },
+    )
+    .await?;
+
+    match next_server_message(read).await?.inner {
+        ServerToPeer::Hello {
+            protocol_version,
+            features,
+        } if protocol_version == PROTOCOL_VERSION => Ok(features.into_iter().collect()),
+        ServerToPeer::Hello {
+            protocol_version, ..
+        } => Err(SignallingError::IncompatibleVersion(protocol_version).into()),
+        ServerToPeer::Error(err) => Err(err.into()),
+        other => Err(eyre!("expected hello, got {other:?}")),
+    }
+}
+
+/// Answer the server's authentication challenge and wait for the id that we are given.
+async fn authenticate(
+    write: &mut SplitSink<ClientWebSocket, Message>,
+    read: &mut SplitStream<ClientWebSocket>,
+    options: &ClientOptions,
+    resume_token: Option<ResumeToken>,
+) -> Result<(PeerId, ResumeToken, bool)> {
+    let challenge = match next_server_message(read).await?.inner {
+        ServerToPeer::AuthChallenge(challenge) => challenge,
+        other => return Err(eyre!("expected auth challenge, got {other:?}")),
+    };
+
+    send_message(
+        write,
+        PeerToServerMessage {
+            job_id: 0,
+            inner: PeerToServer::Authenticate {
+                response: options.credentials.respond(&challenge),
+                peer_id: options.peer_id.clone(),
+                metadata: options.metadata.clone(),
+                resume_token,
+            },
+        },
+    )
+    .await?;
+
+    match next_server_message(read).await?.inner {
+        ServerToPeer::Id {
+            peer_id,
+            resume_token,
+            resumed,
+        } => Ok((peer_id, resume_token, resumed)),
+        ServerToPeer::Error(err) => Err(err.into()),
+        other => Err(eyre!("expected id, got {other:?}")),
+    }
+}
+
+async fn connect(
+    connector: &Connector,
+    options: &ClientOptions,
+    resume_token: Option<ResumeToken>,
+) -> Result<Session> {
+    let ws_stream = connector.open(options).await?;
+
+    let (mut write, mut read) = ws_stream.split();
+
+    let features = hello(&mut write, &mut read).await?;
+
+    let resume_token = resume_token.filter(|_| features.contains(features::RESUME));
+
+    let (peer_id, resume_token, resumed) =
+        authenticate(&mut write, &mut read, options, resume_token).await?;
+
+    Ok(Session {
+        write,
+        read,
+        peer_id,
+        resume_token,
+        resumed,
+        features,
+    })
+}
+
+/// Try to get back onto the server after losing our connection, backing off between attempts. If
+/// we cannot come back as the same peer we come back as a new one, check the session's `peer_id`.
+async fn reconnect(
+    connector: &Connector,
+    options: &ClientOptions,
+    resume_token: ResumeToken,
+    retry_after: Duration,
+    event_tx: &mpsc::Sender<SignallingEvent>,
+) -> Option<Session> {
+    let mut delay = options.reconnect.initial_delay.max(retry_after);
+    let mut options = std::borrow::Cow::Borrowed(options);
+
+    for attempt in 1.. {
+        if options
+            .reconnect
+            .max_attempts
+            .is_some_and(|max_attempts| attempt > max_attempts)
+        {
+            break;
+        }
+
+        let _ = event_tx
+            .send(SignallingEvent::ConnectionState(
+                ConnectionState::Reconnecting { attempt },
+            ))
+            .await;
+
+        tokio::time::sleep(delay).await;
+        let backed_off = delay >= options.reconnect.max_delay;
+        delay = (delay * 2).min(options.reconnect.max_delay);
+
+        match connect(connector, &options, Some(resume_token.clone())).await {
+            Ok(session) => return Some(session),
+            Err(err) => match err.downcast_ref::<SignallingError>() {
+                // NOTE(emily): Most likely the server has not noticed that our old connection is
+                // gone, once it does we can resume (or at least have our id back).
+                Some(SignallingError::IdTaken(_)) if !backed_off => {
+                    tracing::info!(attempt, "id still in use, trying again {err}");
+                }
+                // NOTE(emily): Someone else has it for good or the server does not hand out ids
+                // that are asked for, coming back as someone else beats not coming back.
+                Some(SignallingError::IdTaken(_) | SignallingError::InvalidId(_)) => {
+                    tracing::warn!(
+                        attempt,
+                        "unable to get our id back, asking for a new one {err}"
+                    );
+                    options.to_mut().peer_id = None;
+                }
+                // NOTE(emily): The server answered and said no, trying again will not change that.
+                Some(_) => {
+                    tracing::warn!(attempt, "server refused reconnection {err}");
+                    break;
+                }
+                None => {
+                    tracing::warn!(attempt, "unable to reconnect {err}");
+                }
+            },
+        }
+    }
+
+    None
+}
+
+/// Hand a response to whoever is waiting on it, giving it back if it is not a response to
+/// anything that we are waiting on.
+fn handle_response(
+    pending: &mut PendingRequests,
+    message: ServerToPeerMessage,
+) -> Option<ServerToPeerMessage> {
+    if message.job_id == 0 {
+        return Some(message);
+    }
+
+    let Some(response_tx) = pending.requests.remove(&message.job_id) else {
+        return Some(message);
+    };
+
+    let response = match message.inner {
+        ServerToPeer::ConnectionRequested(peer_id, connection_id) => Ok(
+            SignallingResponse::ConnectionRequested(peer_id, connection_id),
+        ),
+        ServerToPeer::Peers(peers) => Ok(SignallingResponse::Peers(peers)),
+        ServerToPeer::RoomCreated(room_id) => Ok(SignallingResponse::RoomCreated(room_id)),
+        ServerToPeer::InviteCreated(invite, ttl) => {
+            Ok(SignallingResponse::InviteCreated(invite, ttl))
+        }
+        ServerToPeer::RoomJoinRequested(room_id, connection_id) => Ok(
+            SignallingResponse::RoomJoinRequested(room_id, connection_id),
+        ),
+        ServerToPeer::RoomJoined(room_id, host) => {
+            let _ = response_tx.send(Ok(SignallingResponse::RoomJoined(
+                room_id.clone(),
+                host.clone(),
+            )));
+
+            // NOTE(emily): Whoever handles the connection to the host needs to hear about this
+            // too, before the offers and answers that follow it.
+            return Some(ServerToPeerMessage {
+                job_id: 0,
+                inner: ServerToPeer::RoomJoined(room_id, host),
+            });
+        }
+        ServerToPeer::Error(err) => Err(err),
+        inner => {
+            tracing::warn!(?inner, "unexpected response to request");
+            pending.requests.insert(message.job_id, response_tx);
+            return Some(ServerToPeerMessage {
+                job_id: message.job_id,
+                inner,
+            });
+        }
+    };
+
+    let _ = response_tx.send(response);
+
+    None
+}
+
+/// The event that a message from the server turns into, if any.
+fn into_event(inner: ServerToPeer) -> Option<SignallingEvent> {
+    Some(match inner {
+        ServerToPeer::Hello { .. } | ServerToPeer::AuthChallenge(_) => {
+            tracing::warn!("ignoring handshake after authenticating");
+            return None;
+        }
+        ServerToPeer::Id { peer_id, .. } => SignallingEvent::Id(peer_id),
+        ServerToPeer::ConnectionRequest(peer_id, connection_id) => {
+            SignallingEvent::ConectionRequest(peer_id, connection_id)
+        }
+        ServerToPeer::ConnectionRequested(peer_id, connection_id) => {
+            SignallingEvent::ConnectionRequested(peer_id, connection_id)
+        }
+        ServerToPeer::ConnectionRequestClosed(peer_id, connection_id, reason) => {
+            SignallingEvent::ConnectionRequestClosed(peer_id, connection_id, reason)
+        }
+        ServerToPeer::ConnectionAccepted(peer_id, connection_id) => {
+            SignallingEvent::ConnectionAccepted(peer_id, connection_id)
+        }
+        ServerToPeer::ConnectionRejected(peer_id, connection_id, reason) => {
+            SignallingEvent::ConnectionRejected(peer_id, connection_id, reason)
+        }
+        ServerToPeer::Offer(peer_id, offer) => SignallingEvent::Offer(peer_id, offer),
+        ServerToPeer::Answer(peer_id, answer) => SignallingEvent::Answer(peer_id, answer),
+        ServerToPeer::IceCandidate(peer_id, ice_candidate) => {
+            SignallingEvent::IceCandidate(peer_id, ice_candidate)
+        }
+        ServerToPeer::Peers(peers) => SignallingEvent::Peers(peers),
+        ServerToPeer::PeerJoined(peer_info) => SignallingEvent::PeerJoined(peer_info),
+        ServerToPeer::PeerLeft(peer_id) => SignallingEvent::PeerLeft(peer_id),
+        ServerToPeer::IceServers(ice_servers) => SignallingEvent::IceServers(ice_servers),
+        ServerToPeer::RoomCreated(room_id) => SignallingEvent::RoomCreated(room_id),
+        ServerToPeer::RoomJoinRequested(room_id, connection_id) => {
+            SignallingEvent::RoomJoinRequested(room_id, connection_id)
+        }
+        ServerToPeer::RoomJoinRequest(room_id, peer_id, connection_id) => {
+            SignallingEvent::RoomJoinRequest(room_id, peer_id, connection_id)
+        }
+        ServerToPeer::RoomJoined(room_id, host) => SignallingEvent::RoomJoined(room_id, host),
+        ServerToPeer::RoomMemberJoined(room_id, peer_info) => {
+            SignallingEvent::RoomMemberJoined(room_id, peer_info)
+        }
+        ServerToPeer::RoomMemberLeft(room_id, peer_id) => {
+            SignallingEvent::RoomMemberLeft(room_id, peer_id)
+        }
+        ServerToPeer::RoomInvitation(room_id, host) => {
+            SignallingEvent::RoomInvitation(room_id, host)
+        }
+        ServerToPeer::RoomClosed(room_id) => SignallingEvent::RoomClosed(room_id),
+        ServerToPeer::InviteCreated(invite, ttl) => SignallingEvent::InviteCreated(invite, ttl),
+        ServerToPeer::InvitedConnection(peer_id, connection_id, permission) => {
+            SignallingEvent::InvitedConnection(peer_id, connection_id, permission)
+        }
+        ServerToPeer::ServerShuttingDown { retry_after } => {
+            SignallingEvent::ConnectionState(ConnectionState::ServerShuttingDown { retry_after })
+        }
+        ServerToPeer::Error(error) => SignallingEvent::Error(error),
+    })
+}
+
+/// Returned from [`handle_message`] once the server says that it is going away.
+#[derive(Debug)]
+struct ServerShuttingDown {
+    retry_after: Duration,
+}
+
+impl std::fmt::Display for ServerShuttingDown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "server shutting down")
+    }
+}
+
+impl std::error::Error for ServerShuttingDown {}
+
+async fn handle_message(
+    control_tx: mpsc::Sender<SignallingControl>,
+    event_tx: mpsc::Sender<SignallingEvent>,
+    pending: &mut PendingRequests,
+    msg: Message,
+) -> Result<()> {
+    match msg {
+        Text(text) => {
+            let message = serde_json::from_str::<ServerToPeerMessage>(&text)?;
+            tracing::debug!("received {message:?}");
+            let Some(message) = handle_response(pending, message) else {
+                return Ok(());
+            };
+            let kicked = matches!(message.inner, ServerToPeer::Error(SignallingError::Kicked));
+            let shutting_down = match message.inner {
+                ServerToPeer::ServerShuttingDown { retry_after } => Some(retry_after),
+                _ => None,
+            };
+            if let Some(event) = into_event(message.inner) {
+                event_tx.send(event).await?;
+            }
+            if kicked {
+                return Err(SignallingError::Kicked.into());
+            }
+            if let Some(retry_after) = shutting_down {
+                return Err(ServerShuttingDown { retry_after }.into());
+            }
+            Ok(())
+        }
+        Binary(_) | Frame(_) => Err(eyre!("No idea what to do with binary")),
+        Close(_) => Err(eyre!("Going down")),
+        Ping(data) => Ok(control_tx.send(SignallingControl::_Pong(data)).await?),
+        Pong(_) => Ok(()),
+    }
+}
+
+/// Connect to the signal server, returning a way to control the connection, the events that come
+/// from it and the features that the server agreed to.
+#[tracing::instrument]
+pub async fn client(address: &str, options: ClientOptions) -> Result<Signalling> {
+    client_with(Connector::Address(address.to_owned()), options).await
+}
+
+async fn client_with(connector: Connector, options: ClientOptions) -> Result<Signalling> {
+    tracing::info!("starting signal client");
+    let Session {
+        mut write,
+        mut read,
+        peer_id,
+        mut resume_token,
+        features,
+        ..
+    } = connect(&connector, &options, None).await?;
+
+    let (control_tx, mut control_rx) =
+        mpsc::channel::<SignallingControl>(ARBITRARY_SIGNALLING_CHANNEL_LIMIT);
+    let (event_tx, event_rx) = mpsc::channel::<SignallingEvent>(ARBITRARY_SIGNALLING_CHANNEL_LIMIT);
+
+    tracing::info!(%peer_id, ?features, "client signal connected");
+
+    event_tx.send(SignallingEvent::Id(peer_id.clone())).await?;
+
+    // NOTE(emily): If we cannot resume then at least try to come back as the same peer.
+    let mut options = ClientOptions {
+        peer_id: Some(peer_id),
+        ..options
+    };
+
+    tokio::spawn({
+        let control_tx = control_tx.downgrade();
+        async move {
+            let mut pending = PendingRequests::default();
+
+            'session: loop {
+                let mut retry_after = Duration::ZERO;
+
+                match futures::select! {
+                    control = control_rx.recv().fuse() => {
+                        match control {
+                            None => {
+                                tracing::warn!("control_rx None");
+                                // NOTE(emily): Let the server know that we are not coming back.
+                                let _ = write.send(Close(None)).await;
+                                break 'session;
+                            },
+                            Some(control) => handle_control(&mut write, &mut pending, control).await,
+                        }
+                    }
+                    msg = read.next().fuse() => {
+                        if let Some(control_tx) = control_tx.upgrade() {
+                            match msg {
+                                Some(Ok(msg)) => handle_message(control_tx.clone(), event_tx.clone(), &mut pending, msg).await,
+                                None => Err(eyre!("error reading from websocket (None)")),
+                                Some(Err(err)) => Err(eyre!("error reading from websocket ({err})")),
+                            }
+                        } else {
+                            break 'session;
+                        }
+                    }
+                } {
+                    Ok(_ok) => continue,
+                    Err(err) => {
+                        tracing::error!("err {err}");
+
+                        // NOTE(emily): The server does not want us back.
+                        if matches!(err.downcast_ref(), Some(SignallingError::Kicked)) {
+                            let _ = event_tx
+                                .send(SignallingEvent::ConnectionState(
+                                    ConnectionState::Disconnected,
+                                ))
+                                .await;
+                            break;
+                        }
+
+                        if let Some(shutting_down) = err.downcast_ref::<ServerShuttingDown>() {
+                            retry_after = shutting_down.retry_after;
+                        }
+                    }
+                }
+
+                // NOTE(emily): Only get here when the connection has gone bad.
+
+                if event_tx.is_closed() {
+                    break;
+                }
+
+                // NOTE(emily): Anything still waiting on a response is never going to get one.
+                pending.requests.clear();
+
+                match reconnect(
+                    &connector,
+                    &options,
+                    resume_token.clone(),
+                    retry_after,
+                    &event_tx,
+                )
+                .await
+                {
+                    Some(session) => {
+                        tracing::info!(%session.peer_id, session.resumed, ?session.features, "client signal reconnected");
+                        (write, read, resume_token) =
+                            (session.write, session.read, session.resume_token);
+
+                        if options.peer_id.as_ref() != Some(&session.peer_id) {
+                            let _ = event_tx
+                                .send(SignallingEvent::Id(session.peer_id.clone()))
+                                .await;
+                            options.peer_id = Some(session.peer_id);
+                        }
+
+                        let _ = event_tx
+                            .send(SignallingEvent::ConnectionState(
+                                ConnectionState::Reconnected {
+                                    resumed: session.resumed,
+                                },
+                            ))
+                            .await;
+                    }
+                    None => {
+                        let _ = event_tx
+                            .send(SignallingEvent::ConnectionState(
+                                ConnectionState::Disconnected,
+                            ))
+                            .await;
+                        break;
+                    }
+                }
+            }
+
+            tracing::info!("signal client done");
+        }
+    });
+
+    Ok((control_tx, event_rx, features))
+}
+
+/// Send `request` to the server and wait for its response.
+pub async fn request(
+    control: &mpsc::Sender<SignallingControl>,
+    request: SignallingRequest,
+) -> core::result::Result<SignallingResponse, SignallingError> {
+    let (response_tx, response_rx) = oneshot::channel();
+
+    control
+        .send(SignallingControl::Request(request, response_tx))
+        .await
+        .map_err(|_err| SignallingError::Disconnected)?;
+
+    match tokio::time::timeout(REQUEST_TIMEOUT, response_rx).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(_)) => Err(SignallingError::Disconnected),
+        Err(_) => Err(SignallingError::TimedOut),
+    }
+}
+
+/// Ask to connect to `peer_id`, returning the id of the now pending connection request.
+pub async fn request_connection(
+    control: &mpsc::Sender<SignallingControl>,
+    peer_id: PeerId,
+) -> core::result::Result<ConnectionId, SignallingError> {
+    request_connection_with_invite(control, peer_id, None).await
+}
+
+/// Ask to connect to `peer_id` with one of their invites, which has the server accept the
+/// connection straight away. [`SignallingEvent::ConnectionAccepted`] follows.
+pub async fn request_connection_with_invite(
+    control: &mpsc::Sender<SignallingControl>,
+    peer_id: PeerId,
+    invite: Option<InviteToken>,
+) -> core::result::Result<ConnectionId, SignallingError> {
+    match request(
+        control,
+        SignallingRequest::RequestConnection(peer_id, invite),
+    )
+    .await?
+    {
+        SignallingResponse::ConnectionRequested(_, connection_id) => Ok(connection_id),
+        _ => Err(SignallingError::InternalError),
+    }
+}
+
+/// Make an invite that lets other peers connect to us without being accepted by hand, along with
+/// how long it lasts for.
+pub async fn create_invite(
+    control: &mpsc::Sender<SignallingControl>,
+    options: InviteOptions,
+) -> core::result::Result<(InviteToken, Duration), SignallingError> {
+    match request(control, SignallingRequest::CreateInvite(options)).await? {
+        SignallingResponse::InviteCreated(invite, ttl) => Ok((invite, ttl)),
+        _ => Err(SignallingError::InternalError),
+    }
+}
+
+/// Ask the server for everyone else who is online.
+pub async fn list_peers(
+    control: &mpsc::Sender<SignallingControl>,
+) -> core::result::Result<Vec<PeerInfo>, SignallingError> {
+    match request(control, SignallingRequest::ListPeers).await? {
+        SignallingResponse::Peers(peers) => Ok(peers),
+        _ => Err(SignallingError::InternalError),
+    }
+}
+
+/// Open a room that we host.
+pub async fn create_room(
+    control: &mpsc::Sender<SignallingControl>,
+    policy: JoinPolicy,
+) -> core::result::Result<RoomId, SignallingError> {
+    match request(control, SignallingRequest::CreateRoom(policy)).await? {
+        SignallingResponse::RoomCreated(room_id) => Ok(room_id),
+        _ => Err(SignallingError::InternalError),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomJoin {
+    /// In the room, with this host.
+    Joined(PeerId),
+    /// The host has to accept (or reject) this connection request first.
+    Pending(ConnectionId),
+}
+
+/// Ask to join `room_id`.
+pub async fn join_room(
+    control: &mpsc::Sender<SignallingControl>,
+    room_id: RoomId,
+) -> core::result::Result<RoomJoin, SignallingError> {
+    match request(control, SignallingRequest::JoinRoom(room_id)).await? {
+        SignallingResponse::RoomJoined(_, host) => Ok(RoomJoin::Joined(host)),
+        SignallingResponse::RoomJoinRequested(_, connection_id) => {
+            Ok(RoomJoin::Pending(connection_id))
+        }
+        _ => Err(SignallingError::InternalError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpStream;
+
+    type Write = SplitSink<ClientWebSocket, Message>;
+    type Read = SplitStream<ClientWebSocket>;
+
+    async fn start_server(options: ServerOptions) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(vec![listener], None, options, std::future::pending()));
+        address
+    }
+
+    /// Connect without the client so that tests can send the server whatever they like.
+    async fn connect_raw(address: &str) -> (Write, Read) {
+        Connector::Address(address.to_owned())
+            .open(&Default::default())
+            .await
+            .unwrap()
+            .split()
+    }
+
+    async fn connect_authenticated(address: &str) -> (Write, Read) {
+        let (mut write, mut read) = connect_raw(address).await;
+        hello(&mut write, &mut read).await.unwrap();
+        authenticate(&mut write, &mut read, &ClientOptions::default(), None)
+            .await
+            .unwrap();
+        (write, read)
+    }
+
+    /// Log in as whoever `options` says, handing back the server's refusal if there is one.
+    async fn login(
+        address: &str,
+        options: &ClientOptions,
+    ) -> core::result::Result<(Write, Read, PeerId), SignallingError> {
+        let (mut write, mut read) = connect_raw(address).await;
+        hello(&mut write, &mut read).await.unwrap();
+        match authenticate(&mut write, &mut read, options, None).await {
+            Ok((peer_id, _, _)) => Ok((write, read, peer_id)),
+            Err(err) => Err(err.downcast().unwrap()),
+        }
+    }
+
+    async fn expect_error(read: &mut Read) -> SignallingError {
+        match next_server_message(read).await.unwrap().inner {
+            ServerToPeer::Error(err) => err,
+            other => panic!("expected error, got {other:?}"),
+        }
+    }
+
+    /// Log in as "host" with the key made from `seed`.
+    fn bound_options(seed: u8) -> ClientOptions {
+        ClientOptions {
+            credentials: Credentials::Key(ed25519_dalek::SigningKey::from_bytes(&[seed; 32])),
+            peer_id: Some(PeerId::from("host".to_owned())),
+            ..Default::default()
+        }
+    }
+
+    /// Log in as "host" with key 1 and leave again, once `event_rx` has seen them go.
+    async fn bind_and_leave(address: &str, event_rx: &mut mpsc::Receiver<SignallingEvent>) {
+        let (mut write, _read, peer_id) = login(address, &bound_options(1)).await.unwrap();
+        assert_eq!(peer_id.to_string(), "host");
+
+        assert!(matches!(
+            login(address, &bound_options(2)).await.map(|_| ()),
+            Err(SignallingError::IdTaken(_))
+        ));
+
+        // NOTE(emily): Leaving on purpose, so the peer is gone straight away.
+        write.send(Close(None)).await.unwrap();
+        let left = async {
+            while let Some(event) = event_rx.recv().await {
+                if matches!(event, SignallingEvent::PeerLeft(ref left) if *left == peer_id) {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), left)
+            .await
+            .expect("timed out waiting for them to leave");
+    }
+
+    #[tokio::test]
+    async fn test_bound_id_outlives_its_peer() {
+        let address = start_server(Default::default()).await;
+        let (_observer, mut observer_rx, _observer_id) = client_with_id(&address).await;
+
+        bind_and_leave(&address, &mut observer_rx).await;
+
+        let anonymous = ClientOptions {
+            peer_id: Some(PeerId::from("host".to_owned())),
+            ..Default::default()
+        };
+        for options in [bound_options(2), anonymous] {
+            assert!(matches!(
+                login(&address, &options).await.map(|_| ()),
+                Err(SignallingError::IdTaken(_))
+            ));
+        }
+
+        let (_write, _read, peer_id) = login(&address, &bound_options(1)).await.unwrap();
+        assert_eq!(peer_id.to_string(), "host");
+    }
+
+    #[tokio::test]
+    async fn test_bound_id_expires() {
+        let address = start_server(ServerOptions {
+            ids: IdPolicy {
+                bound_id_ttl: Duration::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+        let (_observer, mut observer_rx, _observer_id) = client_with_id(&address).await;
+
+        bind_and_leave(&address, &mut observer_rx).await;
+
+        let (_write, _read, peer_id) = login(&address, &bound_options(2)).await.unwrap();
+        assert_eq!(peer_id.to_string(), "host");
+    }
+
+    #[tokio::test]
+    async fn test_malformed_text_gets_error() {
+        let address = start_server(ServerOptions {
+            malformed_message_limit: None,
+            ..Default::default()
+        })
+        .await;
+        let (mut write, mut read) = connect_authenticated(&address).await;
+
+        for garbage in [
+            "",
+            "not json",
+            "{\"job_id\": 1}",
+            "{\"job_id\": 1, \"inner\": \"Bogus\"}",
+            "{\"job_id\": -1, \"inner\": \"ListPeers\"}",
+        ] {
+            write.send(Message::text(garbage)).await.unwrap();
+            assert!(matches!(
+                expect_error(&mut read).await,
+                SignallingError::MalformedMessage
+            ));
+        }
+
+        // NOTE(emily): Still connected and still being answered.
+        send_message(
+            &mut write,
+            PeerToServerMessage {
+                job_id: 1,
+                inner: PeerToServer::ListPeers,
+            },
+        )
+        .await
+        .unwrap();
+        let message = next_server_message(&mut read).await.unwrap();
+        assert_eq!(message.job_id, 1);
+        assert!(matches!(message.inner, ServerToPeer::Peers(_)));
+    }
+
+    #[tokio::test]
+    async fn test_binary_frame_gets_error() {
+        let address = start_server(Default::default()).await;
+        let (mut write, mut read) = connect_authenticated(&address).await;
+
+        write.send(Message::binary(vec![0, 1, 2, 3])).await.unwrap();
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::UnsupportedFrame
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_malformed_messages_disconnects() {
+        let address = start_server(ServerOptions {
+            malformed_message_limit: Some(2),
+            ..Default::default()
+        })
+        .await;
+        let (mut write, mut read) = connect_authenticated(&address).await;
+
+        write.send(Message::text("garbage")).await.unwrap();
+        write
+            .send(Message::binary(b"garbage".to_vec()))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::MalformedMessage
+        ));
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::UnsupportedFrame
+        ));
+        assert!(next_server_message(&mut read).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_garbage_authentication_is_refused() {
+        let address = start_server(Default::default()).await;
+        let (mut write, mut read) = connect_raw(&address).await;
+        hello(&mut write, &mut read).await.unwrap();
+
+        assert!(matches!(
+            next_server_message(&mut read).await.unwrap().inner,
+            ServerToPeer::AuthChallenge(_)
+        ));
+
+        write.send(Message::text("garbage")).await.unwrap();
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::AuthenticationFailed
+        ));
+        assert!(next_server_message(&mut read).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_version_is_refused() {
+        let address = start_server(Default::default()).await;
+        let (mut write, mut read) = connect_raw(&address).await;
+
+        send_message(
+            &mut write,
+            PeerToServerMessage {
+                job_id: 0,
+                inner: PeerToServer::Hello {
+                    protocol_version: PROTOCOL_VERSION + 1,
+                    features: vec![],
+                },
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::IncompatibleVersion(PROTOCOL_VERSION)
+        ));
+        assert!(next_server_message(&mut read).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_features_are_negotiated() {
+        let address = start_server(ServerOptions {
+            resume_grace: Duration::ZERO,
+            ..Default::default()
+        })
+        .await;
+        let (_control, _event_rx, features) = client(&address, Default::default()).await.unwrap();
+        assert!(!features.contains(features::RESUME));
+
+        let address = start_server(Default::default()).await;
+        let (_control, _event_rx, features) = client(&address, Default::default()).await.unwrap();
+        assert!(features.contains(features::RESUME));
+    }
+
+    #[tokio::test]
+    async fn test_ice_servers_after_login() {
+        let address = start_server(ServerOptions {
+            turn: Some(TurnOptions {
+                urls: vec!["turn:localhost:3478".to_owned()],
+                secret: "north".to_owned(),
+                ttl: Duration::from_secs(60),
+            }),
+            ..Default::default()
+        })
+        .await;
+
+        let (_control, mut event_rx, features) =
+            client(&address, Default::default()).await.unwrap();
+        assert!(features.contains(features::ICE_SERVERS));
+
+        let Some(SignallingEvent::Id(peer_id)) = event_rx.recv().await else {
+            panic!("expected id");
+        };
+        let Some(SignallingEvent::IceServers(ice_servers)) = event_rx.recv().await else {
+            panic!("expected ice servers");
+        };
+        assert_eq!(ice_servers.len(), 1);
+        assert!(ice_servers[0]
+            .username
+            .as_ref()
+            .unwrap()
+            .ends_with(&format!(":{peer_id}")));
+    }
+
+    #[tokio::test]
+    async fn test_garbage_does_not_affect_other_peers() {
+        let address = start_server(ServerOptions {
+            malformed_message_limit: Some(1),
+            ..Default::default()
+        })
+        .await;
+
+        let (control, _event_rx, _features) = client(&address, Default::default()).await.unwrap();
+
+        let (mut write, mut read) = connect_authenticated(&address).await;
+        write.send(Message::binary(vec![0xff; 64])).await.unwrap();
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::UnsupportedFrame
+        ));
+        assert!(next_server_message(&mut read).await.is_err());
+
+        assert!(list_peers(&control).await.is_ok());
+        assert!(client(&address, Default::default()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_gets_error() {
+        let address = start_server(ServerOptions {
+            max_message_size: 128,
+            ..Default::default()
+        })
+        .await;
+        let (mut write, mut read) = connect_authenticated(&address).await;
+
+        write.send(Message::text("a".repeat(129))).await.unwrap();
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::MessageTooLarge
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests_are_all_answered() {
+        let channel_limit = 5;
+        let address = start_server(ServerOptions {
+            channel_limit,
+            rate_limits: RateLimits {
+                connection_requests: RateLimit::new(1000.0, 1000),
+                other: RateLimit::new(1000.0, 1000),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+        let (mut write, mut read) = connect_authenticated(&address).await;
+
+        // NOTE(emily): Nothing is read until everything is sent, half of these are answered with
+        // an error.
+        let count = channel_limit * 8;
+        for job_id in 1..=count {
+            let inner = if job_id % 2 == 0 {
+                PeerToServer::ConnectToPeer(PeerId::from("nobody".to_owned()), None)
+            } else {
+                PeerToServer::ListPeers
+            };
+            send_message(&mut write, PeerToServerMessage { job_id, inner })
+                .await
+                .unwrap();
+        }
+
+        for job_id in 1..=count {
+            let message =
+                tokio::time::timeout(Duration::from_secs(5), next_server_message(&mut read))
+                    .await
+                    .expect("request was never answered")
+                    .unwrap();
+            assert_eq!(message.job_id, job_id);
+            if job_id % 2 == 0 {
+                assert!(matches!(
+                    message.inner,
+                    ServerToPeer::Error(SignallingError::NoSuchPeer(_))
+                ));
+            } else {
+                assert!(matches!(message.inner, ServerToPeer::Peers(_)));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flooding_is_rate_limited() {
+        let address = start_server(ServerOptions {
+            rate_limits: RateLimits {
+                connection_requests: RateLimit::new(0.01, 2),
+                disconnect_after: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+        let (mut write, mut read) = connect_authenticated(&address).await;
+
+        for job_id in 1..=3 {
+            send_message(
+                &mut write,
+                PeerToServerMessage {
+                    job_id,
+                    inner: PeerToServer::ConnectToPeer(PeerId::from("nobody".to_owned()), None),
+                },
+            )
+            .await
+            .unwrap();
+        }
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::NoSuchPeer(_)
+        ));
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::NoSuchPeer(_)
+        ));
+        let message = next_server_message(&mut read).await.unwrap();
+        assert_eq!(message.job_id, 3);
+        assert!(matches!(
+            message.inner,
+            ServerToPeer::Error(SignallingError::RateLimited)
+        ));
+
+        // NOTE(emily): Other kinds of message have their own allowance.
+        send_message(
+            &mut write,
+            PeerToServerMessage {
+                job_id: 4,
+                inner: PeerToServer::ListPeers,
+            },
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            next_server_message(&mut read).await.unwrap().inner,
+            ServerToPeer::Peers(_)
+        ));
+
+        send_message(
+            &mut write,
+            PeerToServerMessage {
+                job_id: 5,
+                inner: PeerToServer::ConnectToPeer(PeerId::from("nobody".to_owned()), None),
+            },
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::RateLimited
+        ));
+        assert!(next_server_message(&mut read).await.is_err());
+    }
+
+    /// Make a bare bones HTTP request and return the status code and body.
+    async fn http_request(
+        address: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+    ) -> (u16, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {token}\r\n"))
+            .unwrap_or_default();
+        stream
+            .write_all(
+                format!(
+                    "{method} {path} HTTP/1.1\r\nHost: localhost\r\n{authorization}Content-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_owned())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("ws://{}", listener.local_addr().unwrap());
+        let admin_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let admin_address = admin_listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            vec![listener],
+            Some(admin_listener),
+            ServerOptions {
+                admin: Some(AdminOptions {
+                    address: admin_address.to_string(),
+                    token: Some("north".to_owned()),
+                }),
+                ..Default::default()
+            },
+            std::future::pending(),
+        ));
+
+        let (mut write, mut read) = connect_raw(&address).await;
+        hello(&mut write, &mut read).await.unwrap();
+        let (peer_id, _, _) = authenticate(&mut write, &mut read, &ClientOptions::default(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            http_request(admin_address, "GET", "/healthz", None).await,
+            (200, "ok".to_owned())
+        );
+        assert_eq!(
+            http_request(admin_address, "GET", "/peers", None).await.0,
+            401
+        );
+
+        let (status, body) = http_request(admin_address, "GET", "/peers", Some("north")).await;
+        assert_eq!(status, 200);
+        assert!(body.contains(&format!("\"peer_id\":\"{peer_id}\"")));
+
+        let kick = format!("/peers/{peer_id}/kick");
+        assert_eq!(
+            http_request(admin_address, "POST", &kick, Some("south"))
+                .await
+                .0,
+            401
+        );
+        assert_eq!(
+            http_request(admin_address, "POST", &kick, Some("north"))
+                .await
+                .0,
+            204
+        );
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::Kicked
+        ));
+        assert!(next_server_message(&mut read).await.is_err());
+        assert_eq!(
+            http_request(admin_address, "POST", &kick, Some("north"))
+                .await
+                .0,
+            404
+        );
+
+        let (status, body) = http_request(admin_address, "GET", "/metrics", Some("north")).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("signal_kicked_total 1\n"));
+        assert!(body.contains("signal_connections_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_admin_needs_token_off_loopback() {
+        let admin = |address: &tokio::net::TcpListener, token: Option<&str>| AdminOptions {
+            address: address.local_addr().unwrap().to_string(),
+            token: token.map(str::to_owned),
+        };
+
+        let admin_listener = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let options = ServerOptions {
+            admin: Some(admin(&admin_listener, None)),
+            ..Default::default()
+        };
+        assert!(serve(vec![], Some(admin_listener), options, async {})
+            .await
+            .is_err());
+
+        let admin_listener = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let options = ServerOptions {
+            admin: Some(admin(&admin_listener, Some("north"))),
+            ..Default::default()
+        };
+        assert!(serve(vec![], Some(admin_listener), options, async {})
+            .await
+            .is_ok());
+    }
+
+    /// A client that is online, along with its id.
+    async fn client_with_id(
+        address: &str,
+    ) -> (
+        mpsc::Sender<SignallingControl>,
+        mpsc::Receiver<SignallingEvent>,
+        PeerId,
+    ) {
+        let (control, mut event_rx, _features) = client(address, Default::default()).await.unwrap();
+        let Some(SignallingEvent::Id(peer_id)) = event_rx.recv().await else {
+            panic!("expected id");
+        };
+        (control, event_rx, peer_id)
+    }
+
+    /// The next event, skipping over everyone else coming and going.
+    pub(crate) async fn next_event(
+        event_rx: &mut mpsc::Receiver<SignallingEvent>,
+    ) -> SignallingEvent {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), event_rx.recv())
+                .await
+                .expect("timed out waiting for an event")
+                .expect("client went away")
+            {
+                SignallingEvent::PeerJoined(_) | SignallingEvent::PeerLeft(_) => {}
+                event => return event,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_connection_is_surfaced() {
+        let address = start_server(Default::default()).await;
+
+        let (host, mut host_rx, host_id) = client_with_id(&address).await;
+        let (viewer, mut viewer_rx, viewer_id) = client_with_id(&address).await;
+
+        let connection_id = request_connection(&viewer, host_id.clone()).await.unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConectionRequest(peer_id, id) if peer_id == viewer_id && id == connection_id
+        ));
+
+        host.send(SignallingControl::RejectConnection(connection_id))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::ConnectionRejected(peer_id, id, RejectionReason::Declined)
+                if peer_id == host_id && id == connection_id
+        ));
+
+        // NOTE(emily): Answering again is ignored, and the host is still fine.
+        host.send(SignallingControl::AcceptConnection(connection_id))
+            .await
+            .unwrap();
+        assert!(list_peers(&host).await.is_ok());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), next_event(&mut viewer_rx))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_requests_expire() {
+        let address = start_server(ServerOptions {
+            request_ttl: Duration::from_millis(100),
+            ..Default::default()
+        })
+        .await;
+
+        let (host, mut host_rx, host_id) = client_with_id(&address).await;
+        let (viewer, mut viewer_rx, viewer_id) = client_with_id(&address).await;
+
+        let connection_id = request_connection(&viewer, host_id.clone()).await.unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConectionRequest(..)
+        ));
+
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::ConnectionRequestClosed(peer_id, id, RequestClosedReason::Expired)
+                if peer_id == host_id && id == connection_id
+        ));
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConnectionRequestClosed(peer_id, id, RequestClosedReason::Expired)
+                if peer_id == viewer_id && id == connection_id
+        ));
+
+        // NOTE(emily): Too late.
+        host.send(SignallingControl::AcceptConnection(connection_id))
+            .await
+            .unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), next_event(&mut viewer_rx))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_requests_can_be_cancelled() {
+        let address = start_server(Default::default()).await;
+
+        let (_host, mut host_rx, host_id) = client_with_id(&address).await;
+        let (viewer, mut viewer_rx, viewer_id) = client_with_id(&address).await;
+
+        let connection_id = request_connection(&viewer, host_id.clone()).await.unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConectionRequest(..)
+        ));
+
+        viewer
+            .send(SignallingControl::CancelConnection(connection_id))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConnectionRequestClosed(peer_id, id, RequestClosedReason::Cancelled)
+                if peer_id == viewer_id && id == connection_id
+        ));
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::ConnectionRequestClosed(peer_id, id, RequestClosedReason::Cancelled)
+                if peer_id == host_id && id == connection_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_connection_requests_closed_when_peer_leaves() {
+        let address = start_server(Default::default()).await;
+
+        let (host, mut host_rx, host_id) = client_with_id(&address).await;
+        let (viewer, mut viewer_rx, _) = client_with_id(&address).await;
+
+        let connection_id = request_connection(&viewer, host_id.clone()).await.unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConectionRequest(..)
+        ));
+
+        // NOTE(emily): Dropping the control closes the connection on purpose, so the host is gone
+        // for good straight away.
+        drop(host);
+
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::ConnectionRequestClosed(peer_id, id, RequestClosedReason::PeerDisconnected)
+                if peer_id == host_id && id == connection_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_token_authentication() {
+        let address = start_server(ServerOptions {
+            auth: AuthOptions {
+                token: Some("secret".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        let token = |token: &str| ClientOptions {
+            credentials: Credentials::Token(token.to_owned()),
+            ..Default::default()
+        };
+
+        assert!(login(&address, &token("secret")).await.is_ok());
+        assert!(client(&address, token("secret")).await.is_ok());
+
+        for options in [token("wrong"), ClientOptions::default()] {
+            assert!(matches!(
+                login(&address, &options).await.map(|_| ()),
+                Err(SignallingError::AuthenticationFailed)
+            ));
+        }
+
+        // NOTE(emily): Refused peers are not left connected.
+        let (mut write, mut read) = connect_raw(&address).await;
+        hello(&mut write, &mut read).await.unwrap();
+        assert!(authenticate(&mut write, &mut read, &token("wrong"), None)
+            .await
+            .is_err());
+        assert!(next_server_message(&mut read).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_key_authentication() {
+        let key = |seed| ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+        let address = start_server(ServerOptions {
+            auth: AuthOptions {
+                authorized_keys: vec![key(1).verifying_key()],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        let options = |seed| ClientOptions {
+            credentials: Credentials::Key(key(seed)),
+            ..Default::default()
+        };
+
+        assert!(login(&address, &options(1)).await.is_ok());
+        assert!(matches!(
+            login(&address, &options(2)).await.map(|_| ()),
+            Err(SignallingError::AuthenticationFailed)
+        ));
+        assert!(matches!(
+            login(&address, &ClientOptions::default()).await.map(|_| ()),
+            Err(SignallingError::AuthenticationFailed)
+        ));
+
+        // NOTE(emily): A signature over some other challenge does not count.
+        let (mut write, mut read) = connect_raw(&address).await;
+        hello(&mut write, &mut read).await.unwrap();
+        assert!(matches!(
+            next_server_message(&mut read).await.unwrap().inner,
+            ServerToPeer::AuthChallenge(_)
+        ));
+        send_message(
+            &mut write,
+            PeerToServerMessage {
+                job_id: 0,
+                inner: PeerToServer::Authenticate {
+                    response: options(1).credentials.respond(b"not the challenge"),
+                    peer_id: None,
+                    metadata: Default::default(),
+                    resume_token: None,
+                },
+            },
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            expect_error(&mut read).await,
+            SignallingError::AuthenticationFailed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_requested_ids() {
+        let address = start_server(Default::default()).await;
+        let asking_for = |peer_id: &str| ClientOptions {
+            peer_id: Some(PeerId::from(peer_id.to_owned())),
+            ..Default::default()
+        };
+
+        let (_control, mut event_rx, _features) =
+            client(&address, asking_for("host-1")).await.unwrap();
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(SignallingEvent::Id(peer_id)) if peer_id.to_string() == "host-1"
+        ));
+
+        assert!(matches!(
+            login(&address, &asking_for("host-1")).await.map(|_| ()),
+            Err(SignallingError::IdTaken(_))
+        ));
+        for peer_id in ["Host", "host 1", ""] {
+            assert!(matches!(
+                login(&address, &asking_for(peer_id)).await.map(|_| ()),
+                Err(SignallingError::InvalidId(_))
+            ));
+        }
+
+        // NOTE(emily): Everyone else is given a fresh id of the configured shape.
+        let (_write, _read, peer_id) = login(&address, &Default::default()).await.unwrap();
+        let policy = IdPolicy::default();
+        assert_eq!(peer_id.to_string().len(), policy.length);
+        assert!(peer_id
+            .to_string()
+            .chars()
+            .all(|c| policy.charset.contains(c)));
+    }
+
+    #[tokio::test]
+    async fn test_requested_ids_can_be_disallowed() {
+        let address = start_server(ServerOptions {
+            ids: IdPolicy {
+                allow_requested: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        assert!(matches!(
+            login(
+                &address,
+                &ClientOptions {
+                    peer_id: Some(PeerId::from("host".to_owned())),
+                    ..Default::default()
+                }
+            )
+            .await
+            .map(|_| ()),
+            Err(SignallingError::InvalidId(_))
+        ));
+        assert!(login(&address, &Default::default()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_presence() {
+        let address = start_server(Default::default()).await;
+
+        let (host, mut host_rx, host_id) = client_with_id(&address).await;
+
+        let (viewer, _viewer_rx, _features) = client(
+            &address,
+            ClientOptions {
+                metadata: PeerMetadata {
+                    display_name: Some("viewer".to_owned()),
+                    role: PeerRole::Viewer,
+                    capabilities: vec!["audio".to_owned()],
+                },
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // NOTE(emily): next_event skips over exactly these, so wait for them here.
+        let presence = Duration::from_secs(5);
+        let Ok(Some(SignallingEvent::PeerJoined(joined))) =
+            tokio::time::timeout(presence, host_rx.recv()).await
+        else {
+            panic!("expected a peer to join");
+        };
+        assert_eq!(joined.metadata.display_name.as_deref(), Some("viewer"));
+        assert_eq!(joined.metadata.role, PeerRole::Viewer);
+        assert_eq!(joined.metadata.capabilities, ["audio"]);
+
+        // NOTE(emily): Everyone else, but never ourselves.
+        let peers = list_peers(&host).await.unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].peer_id, joined.peer_id);
+        assert_eq!(peers[0].metadata.display_name.as_deref(), Some("viewer"));
+        let peers = list_peers(&viewer).await.unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].peer_id, host_id);
+
+        drop(viewer);
+        assert!(matches!(
+            tokio::time::timeout(presence, host_rx.recv()).await,
+            Ok(Some(SignallingEvent::PeerLeft(peer_id))) if peer_id == joined.peer_id
+        ));
+        assert!(list_peers(&host).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_requests_get_their_own_responses() {
+        let address = start_server(Default::default()).await;
+
+        let (host, _host_rx, host_id) = client_with_id(&address).await;
+        let (viewer, mut viewer_rx, _viewer_id) = client_with_id(&address).await;
+
+        // NOTE(emily): Failures come back to whoever asked, not on the event stream.
+        assert!(matches!(
+            request_connection(&viewer, PeerId::from("nobody".to_owned())).await,
+            Err(SignallingError::NoSuchPeer(peer_id)) if peer_id.to_string() == "nobody"
+        ));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), next_event(&mut viewer_rx))
+                .await
+                .is_err()
+        );
+
+        let (to_host, to_nobody, peers, invite) = tokio::join!(
+            request_connection(&viewer, host_id.clone()),
+            request_connection(&viewer, PeerId::from("nobody".to_owned())),
+            list_peers(&viewer),
+            create_invite(&host, Default::default()),
+        );
+        assert!(to_host.is_ok());
+        assert!(matches!(to_nobody, Err(SignallingError::NoSuchPeer(_))));
+        assert_eq!(peers.unwrap()[0].peer_id, host_id);
+        assert!(invite.is_ok());
+    }
+
+    /// How a [`Proxy`] breaks the connections going through it.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Break {
+        /// Both ends see the connection go.
+        Cut,
+        /// Only the client sees it go, the server is left waiting on a connection that will never
+        /// say anything again.
+        Hang,
+    }
+
+    /// Sits between clients and the server so that tests can break their connections.
+    struct Proxy {
+        address: String,
+        break_tx: tokio::sync::watch::Sender<Option<Break>>,
+    }
+
+    impl Proxy {
+        async fn start(server: &str) -> Self {
+            let server = server.strip_prefix("ws://").unwrap().to_owned();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = format!("ws://{}", listener.local_addr().unwrap());
+            let (break_tx, _) = tokio::sync::watch::channel(None);
+
+            tokio::spawn({
+                let break_tx = break_tx.clone();
+                async move {
+                    while let Ok((client, _)) = listener.accept().await {
+                        let server = TcpStream::connect(&server).await.unwrap();
+                        // NOTE(emily): Only breaks that happen after this connection was made.
+                        let mut break_rx = break_tx.subscribe();
+
+                        tokio::spawn(async move {
+                            let (mut client_read, mut client_write) = client.into_split();
+                            let (mut server_read, mut server_write) = server.into_split();
+
+                            tokio::select! {
+                                _ = tokio::io::copy(&mut client_read, &mut server_write) => return,
+                                _ = tokio::io::copy(&mut server_read, &mut client_write) => return,
+                                _ = break_rx.changed() => {}
+                            }
+
+                            if *break_rx.borrow() == Some(Break::Hang) {
+                                drop((client_read, client_write));
+                                let _server = (server_read, server_write);
+                                std::future::pending::<()>().await;
+                            }
+                        });
+                    }
+                }
+            });
+
+            Self { address, break_tx }
+        }
+
+        fn break_connections(&self, how: Break) {
+            self.break_tx.send_replace(Some(how));
+        }
+    }
+
+    fn reconnecting_options() -> ClientOptions {
+        ClientOptions {
+            reconnect: ReconnectOptions {
+                initial_delay: Duration::from_millis(50),
+                max_delay: Duration::from_millis(400),
+                max_attempts: None,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Wait for the client to reconnect, returning whether it resumed and any new id that it got.
+    async fn reconnected(event_rx: &mut mpsc::Receiver<SignallingEvent>) -> (bool, Option<PeerId>) {
+        let mut new_id = None;
+        loop {
+            match next_event(event_rx).await {
+                SignallingEvent::ConnectionState(ConnectionState::Reconnected { resumed }) => {
+                    return (resumed, new_id)
+                }
+                SignallingEvent::ConnectionState(ConnectionState::Reconnecting { .. }) => {}
+                SignallingEvent::Id(peer_id) => new_id = Some(peer_id),
+                other => panic!("expected to reconnect, got {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_resumes_session() {
+        let address = start_server(Default::default()).await;
+        let proxy = Proxy::start(&address).await;
+        let (_control, mut event_rx, _) = client(&proxy.address, reconnecting_options())
+            .await
+            .unwrap();
+        let Some(SignallingEvent::Id(peer_id)) = event_rx.recv().await else {
+            panic!("expected id");
+        };
+
+        let (other, _other_rx, other_id) = client_with_id(&address).await;
+
+        // NOTE(emily): Twice, the token handed out on resuming has to work too.
+        for _ in 0..2 {
+            proxy.break_connections(Break::Cut);
+            assert_eq!(reconnected(&mut event_rx).await, (true, None));
+        }
+
+        // NOTE(emily): Still the same peer as far as anyone else is concerned.
+        assert!(list_peers(&other)
+            .await
+            .unwrap()
+            .iter()
+            .any(|info| info.peer_id == peer_id));
+        let connection_id = request_connection(&other, peer_id.clone()).await.unwrap();
+        assert!(matches!(
+            next_event(&mut event_rx).await,
+            SignallingEvent::ConectionRequest(requester, id) if requester == other_id && id == connection_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_session_expired() {
+        let address = start_server(ServerOptions {
+            resume_grace: Duration::ZERO,
+            ..Default::default()
+        })
+        .await;
+        let proxy = Proxy::start(&address).await;
+        let (_control, mut event_rx, _) = client(&proxy.address, reconnecting_options())
+            .await
+            .unwrap();
+        let Some(SignallingEvent::Id(peer_id)) = event_rx.recv().await else {
+            panic!("expected id");
+        };
+
+        proxy.break_connections(Break::Cut);
+
+        // NOTE(emily): Forgotten, but the id is free again so we get it back.
+        assert_eq!(reconnected(&mut event_rx).await, (false, None));
+
+        let (other, _other_rx, _) = client_with_id(&address).await;
+        assert!(list_peers(&other)
+            .await
+            .unwrap()
+            .iter()
+            .any(|info| info.peer_id == peer_id));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_whilst_server_holds_old_id() {
+        let address = start_server(ServerOptions {
+            ping_interval: Duration::from_millis(100),
+            idle_timeout: Duration::from_millis(300),
+            ..Default::default()
+        })
+        .await;
+        let proxy = Proxy::start(&address).await;
+        let (_control, mut event_rx, _) = client(&proxy.address, reconnecting_options())
+            .await
+            .unwrap();
+        let Some(SignallingEvent::Id(_)) = event_rx.recv().await else {
+            panic!("expected id");
+        };
+
+        // NOTE(emily): The server thinks that we are still connected until we go idle, so the
+        // first few attempts find our id taken.
+        proxy.break_connections(Break::Hang);
+
+        assert_eq!(reconnected(&mut event_rx).await, (true, None));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_falls_back_to_new_id() {
+        let address = start_server(ServerOptions {
+            resume_grace: Duration::ZERO,
+            ids: IdPolicy {
+                allow_requested: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+        let proxy = Proxy::start(&address).await;
+        let (_control, mut event_rx, _) = client(&proxy.address, reconnecting_options())
+            .await
+            .unwrap();
+        let Some(SignallingEvent::Id(peer_id)) = event_rx.recv().await else {
+            panic!("expected id");
+        };
+
+        proxy.break_connections(Break::Cut);
+
+        let (resumed, new_id) = reconnected(&mut event_rx).await;
+        assert!(!resumed);
+        assert!(new_id.is_some_and(|new_id| new_id != peer_id));
+    }
+
+    const SDP: &str = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n";
+    const CANDIDATE: &str = "candidate:1 1 udp 2130706431 192.168.1.2 50000 typ host";
+
+    #[tokio::test]
+    async fn test_shutdown_tells_peers() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("ws://{}", listener.local_addr().unwrap());
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            vec![listener],
+            None,
+            ServerOptions {
+                shutdown_retry_after: Duration::from_millis(200),
+                ..Default::default()
+            },
+            async move {
+                let _ = shutdown_rx.await;
+            },
+        ));
+
+        let (_write, mut read) = connect_authenticated(&address).await;
+        let (_control, mut event_rx, features) = client(
+            &address,
+            ClientOptions {
+                reconnect: ReconnectOptions {
+                    max_attempts: Some(1),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(features.contains(features::SHUTDOWN_NOTICE));
+        assert!(matches!(
+            next_event(&mut event_rx).await,
+            SignallingEvent::Id(_)
+        ));
+
+        shutdown_tx.send(()).unwrap();
+
+        let inner = loop {
+            match next_server_message(&mut read).await.unwrap().inner {
+                ServerToPeer::PeerJoined(_) | ServerToPeer::PeerLeft(_) => {}
+                inner => break inner,
+            }
+        };
+        assert!(matches!(
+            inner,
+            ServerToPeer::ServerShuttingDown { retry_after } if retry_after == Duration::from_millis(200)
+        ));
+        let close = loop {
+            match read.next().await {
+                Some(Ok(Ping(_))) => {}
+                other => break other,
+            }
+        };
+        assert!(matches!(
+            close,
+            Some(Ok(Close(Some(CloseFrame {
+                code: CloseCode::Away,
+                ..
+            }))))
+        ));
+
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(
+            next_event(&mut event_rx).await,
+            SignallingEvent::ConnectionState(ConnectionState::ServerShuttingDown { retry_after })
+                if retry_after == Duration::from_millis(200)
+        ));
+        assert!(matches!(
+            next_event(&mut event_rx).await,
+            SignallingEvent::ConnectionState(ConnectionState::Reconnecting { attempt: 1 })
+        ));
+        // NOTE(emily): Nobody is listening any more.
+        assert!(matches!(
+            next_event(&mut event_rx).await,
+            SignallingEvent::ConnectionState(ConnectionState::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_relay_needs_accepted_connection() {
+        let address = start_server(Default::default()).await;
+
+        let (host, mut host_rx, host_id) = client_with_id(&address).await;
+        let (viewer, mut viewer_rx, viewer_id) = client_with_id(&address).await;
+
+        viewer
+            .send(SignallingControl::Offer(host_id.clone(), SDP.to_owned()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::Error(SignallingError::NotConnected(peer_id)) if peer_id == host_id
+        ));
+
+        let connection_id = request_connection(&viewer, host_id.clone()).await.unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConectionRequest(..)
+        ));
+
+        // NOTE(emily): Still pending.
+        host.send(SignallingControl::IceCandidate(
+            viewer_id.clone(),
+            CANDIDATE.to_owned(),
+        ))
+        .await
+        .unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::Error(SignallingError::NotConnected(_))
+        ));
+
+        host.send(SignallingControl::AcceptConnection(connection_id))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::ConnectionAccepted(..)
+        ));
+
+        viewer
+            .send(SignallingControl::Offer(host_id.clone(), SDP.to_owned()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::Offer(peer_id, offer) if peer_id == viewer_id && offer == SDP
+        ));
+
+        host.send(SignallingControl::Answer(
+            viewer_id.clone(),
+            "<script>".to_owned(),
+        ))
+        .await
+        .unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::Error(SignallingError::InvalidPayload)
+        ));
+
+        host.send(SignallingControl::IceCandidate(
+            viewer_id.clone(),
+            CANDIDATE.to_owned(),
+        ))
+        .await
+        .unwrap();
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::IceCandidate(peer_id, candidate) if peer_id == host_id && candidate == CANDIDATE
+        ));
+
+        // NOTE(emily): Someone else that never asked gets nowhere.
+        let (other, mut other_rx, _) = client_with_id(&address).await;
+        other
+            .send(SignallingControl::Offer(host_id, SDP.to_owned()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut other_rx).await,
+            SignallingEvent::Error(SignallingError::NotConnected(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_room_members_are_announced() {
+        let address = start_server(Default::default()).await;
+
+        let (host, mut host_rx, host_id) = client_with_id(&address).await;
+        let room_id = create_room(&host, JoinPolicy::AutoAccept).await.unwrap();
+
+        let (first, mut first_rx, first_id) = client_with_id(&address).await;
+        assert!(matches!(
+            join_room(&first, room_id.clone()).await,
+            Ok(RoomJoin::Joined(host)) if host == host_id
+        ));
+        assert!(matches!(
+            next_event(&mut first_rx).await,
+            SignallingEvent::RoomJoined(joined, host) if joined == room_id && host == host_id
+        ));
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::RoomMemberJoined(joined, info) if joined == room_id && info.peer_id == first_id
+        ));
+
+        let (second, mut second_rx, second_id) = client_with_id(&address).await;
+        assert!(matches!(
+            join_room(&second, room_id.clone()).await,
+            Ok(RoomJoin::Joined(_))
+        ));
+        for event_rx in [&mut host_rx, &mut first_rx] {
+            assert!(matches!(
+                next_event(event_rx).await,
+                SignallingEvent::RoomMemberJoined(_, info) if info.peer_id == second_id
+            ));
+        }
+        assert!(matches!(
+            next_event(&mut second_rx).await,
+            SignallingEvent::RoomJoined(..)
+        ));
+
+        first
+            .send(SignallingControl::LeaveRoom(room_id.clone()))
+            .await
+            .unwrap();
+        for event_rx in [&mut host_rx, &mut second_rx] {
+            assert!(matches!(
+                next_event(event_rx).await,
+                SignallingEvent::RoomMemberLeft(_, peer_id) if peer_id == first_id
+            ));
+        }
+
+        // NOTE(emily): The host going away closes the room for everyone.
+        drop(host);
+        drop(host_rx);
+        assert!(matches!(
+            next_event(&mut second_rx).await,
+            SignallingEvent::RoomClosed(closed) if closed == room_id
+        ));
+        assert!(matches!(
+            join_room(&first, room_id.clone()).await,
+            Err(SignallingError::NoSuchRoom(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_room_policies() {
+        let address = start_server(Default::default()).await;
+
+        let (host, mut host_rx, host_id) = client_with_id(&address).await;
+        let room_id = create_room(&host, JoinPolicy::Approve).await.unwrap();
+
+        // NOTE(emily): Approval goes through a connection request to the host.
+        let (viewer, mut viewer_rx, viewer_id) = client_with_id(&address).await;
+        let Ok(RoomJoin::Pending(connection_id)) = join_room(&viewer, room_id.clone()).await else {
+            panic!("expected to wait for approval");
+        };
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::RoomJoinRequest(_, peer_id, id) if peer_id == viewer_id && id == connection_id
+        ));
+        host.send(SignallingControl::AcceptConnection(connection_id))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::RoomJoined(_, host) if host == host_id
+        ));
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::RoomMemberJoined(_, info) if info.peer_id == viewer_id
+        ));
+
+        // NOTE(emily): Only the host gets to change the room.
+        viewer
+            .send(SignallingControl::SetRoomPolicy(
+                room_id.clone(),
+                JoinPolicy::AutoAccept,
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::Error(SignallingError::NotRoomHost)
+        ));
+
+        host.send(SignallingControl::SetRoomPolicy(
+            room_id.clone(),
+            JoinPolicy::InviteOnly,
+        ))
+        .await
+        .unwrap();
+
+        let (guest, mut guest_rx, guest_id) = client_with_id(&address).await;
+        assert!(matches!(
+            join_room(&guest, room_id.clone()).await,
+            Err(SignallingError::NotInvited)
+        ));
+
+        host.send(SignallingControl::InviteToRoom(room_id.clone(), guest_id))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut guest_rx).await,
+            SignallingEvent::RoomInvitation(invited, host) if invited == room_id && host == host_id
+        ));
+        assert!(matches!(
+            join_room(&guest, room_id.clone()).await,
+            Ok(RoomJoin::Joined(host)) if host == host_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invites_skip_acceptance() {
+        let address = start_server(Default::default()).await;
+
+        let (host, mut host_rx, host_id) = client_with_id(&address).await;
+        let (invite, ttl) = create_invite(
+            &host,
+            InviteOptions {
+                ttl: Duration::from_secs(7 * 24 * 60 * 60),
+                max_uses: Some(1),
+                permission: Permission::ViewOnly,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(ttl, ServerOptions::default().max_invite_ttl);
+
+        let (viewer, mut viewer_rx, viewer_id) = client_with_id(&address).await;
+        let connection_id =
+            request_connection_with_invite(&viewer, host_id.clone(), Some(invite.clone()))
+                .await
+                .unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::InvitedConnection(peer_id, id, Permission::ViewOnly)
+                if peer_id == viewer_id && id == connection_id
+        ));
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::ConnectionAccepted(peer_id, id) if peer_id == host_id && id == connection_id
+        ));
+
+        // NOTE(emily): Used up.
+        let (other, _other_rx, _) = client_with_id(&address).await;
+        assert!(matches!(
+            request_connection_with_invite(&other, host_id.clone(), Some(invite)).await,
+            Err(SignallingError::InvalidInvite)
+        ));
+
+        let (invite, _) = create_invite(
+            &host,
+            InviteOptions {
+                max_uses: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // NOTE(emily): Only good for connecting to whoever made it.
+        assert!(matches!(
+            request_connection_with_invite(&other, viewer_id, Some(invite.clone())).await,
+            Err(SignallingError::InvalidInvite)
+        ));
+        assert!(
+            request_connection_with_invite(&other, host_id.clone(), Some(invite.clone()))
+                .await
+                .is_ok()
+        );
+
+        // NOTE(emily): Only whoever made it can revoke it, the round trip makes sure that the
+        // server has dealt with the revoke before we try it again.
+        viewer
+            .send(SignallingControl::RevokeInvite(invite.clone()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::Error(SignallingError::InvalidInvite)
+        ));
+        host.send(SignallingControl::RevokeInvite(invite.clone()))
+            .await
+            .unwrap();
+        list_peers(&host).await.unwrap();
+        assert!(matches!(
+            request_connection_with_invite(&other, host_id.clone(), Some(invite)).await,
+            Err(SignallingError::InvalidInvite)
+        ));
+
+        let (invite, _) = create_invite(
+            &host,
+            InviteOptions {
+                ttl: Duration::from_millis(100),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(matches!(
+            request_connection_with_invite(&other, host_id, Some(invite)).await,
+            Err(SignallingError::InvalidInvite)
+        ));
+    }
 }
@@ -1,16 +1,366 @@
+use std::path::PathBuf;
+
 use clap::Parser;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter, Targets};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry};
+
+mod config;
+
+use config::{AdminConfig, Config, LogConfig, LogFormat, TlsConfig, TurnConfig};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    address: String,
+    /// Addresses to listen on (e.g. 0.0.0.0:8080), added to any from the config.
+    addresses: Vec<String>,
+    /// TOML config file, flags take priority over anything in it.
+    #[clap(long)]
+    config: Option<PathBuf>,
+    /// PEM certificate chain to serve TLS with.
+    #[clap(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key to serve TLS with.
+    #[clap(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Seconds before a pending connection request expires [default: 60].
+    #[clap(long)]
+    request_ttl: Option<u64>,
+    /// Seconds a disconnected peer has to resume before it is forgotten, 0 to disable [default: 30].
+    #[clap(long)]
+    resume_grace: Option<u64>,
+    /// Seconds between pings [default: 30].
+    #[clap(long)]
+    ping_interval: Option<u64>,
+    /// Seconds without hearing from a peer before disconnecting them [default: 90].
+    #[clap(long)]
+    idle_timeout: Option<u64>,
+    /// Messages that can queue up for a peer [default: 5].
+    #[clap(long)]
+    channel_limit: Option<usize>,
+    /// Disconnect peers after this many malformed messages, 0 to never disconnect them [default: 5].
+    #[clap(long)]
+    malformed_message_limit: Option<u32>,
+    /// Most peers that can be connected at once.
+    #[clap(long)]
+    max_peers: Option<usize>,
+    /// Most connection requests a peer can have pending at once [default: 10].
+    #[clap(long)]
+    max_pending_requests: Option<usize>,
+    /// Biggest message (in bytes) that peers can send [default: 65536].
+    #[clap(long)]
+    max_message_size: Option<usize>,
+    /// Longest (in seconds) that invites can last for [default: 86400].
+    #[clap(long)]
+    max_invite_ttl: Option<u64>,
+    /// Seconds that peers are told to wait before reconnecting when the server shuts down [default: 5].
+    #[clap(long)]
+    shutdown_retry_after: Option<u64>,
+    /// Length of generated ids [default: 5].
+    #[clap(long)]
+    id_length: Option<usize>,
+    /// Characters that generated ids are made of.
+    #[clap(long)]
+    id_charset: Option<String>,
+    /// Do not let peers ask for a specific id.
+    #[clap(long)]
+    no_requested_ids: bool,
+    /// Token that peers must present to connect.
+    #[clap(long)]
+    token: Option<String>,
+    /// Hex encoded ed25519 public key that is allowed to connect (can be repeated).
+    #[clap(long = "authorized-key")]
+    authorized_keys: Vec<String>,
+    /// Secret shared with the TURN server, enables handing out TURN credentials.
+    #[clap(long, requires = "turn_urls")]
+    turn_secret: Option<String>,
+    /// Url of the TURN server (can be repeated).
+    #[clap(long = "turn-url")]
+    turn_urls: Vec<String>,
+    /// Seconds that TURN credentials are valid for [default: 86400].
+    #[clap(long)]
+    turn_ttl: Option<u64>,
+    /// Address to serve /healthz, /peers, /connection-requests and /metrics on.
+    #[clap(long)]
+    admin_address: Option<String>,
+    /// Token needed to use the admin endpoints (and to kick peers), required unless the admin
+    /// address is a loopback address.
+    #[clap(long)]
+    admin_token: Option<String>,
+    /// How log lines look [default: pretty].
+    #[clap(long, value_enum)]
+    log_format: Option<LogFormat>,
+    /// Log filter directives (e.g. `info,signal=debug`), RUST_LOG takes priority [default: info].
+    #[clap(long)]
+    log_level: Option<String>,
+    /// File to append audit events (who connected to whom and when) to.
+    #[clap(long)]
+    audit_log: Option<PathBuf>,
+}
+
+impl Args {
+    /// Lay these flags over the top of `config`.
+    fn apply(self, config: &mut Config) {
+        config.listen.extend(self.addresses);
+
+        if let (Some(cert), Some(key)) = (self.tls_cert, self.tls_key) {
+            config.tls = Some(TlsConfig {
+                cert,
+                key,
+                reload_interval: config.tls.as_ref().and_then(|tls| tls.reload_interval),
+            });
+        }
+
+        macro_rules! set {
+            ($($field:ident),*) => {
+                $(if self.$field.is_some() {
+                    config.$field = self.$field;
+                })*
+            };
+        }
+
+        set!(
+            request_ttl,
+            resume_grace,
+            ping_interval,
+            idle_timeout,
+            channel_limit,
+            malformed_message_limit,
+            max_peers,
+            max_pending_requests,
+            max_message_size,
+            max_invite_ttl,
+            shutdown_retry_after
+        );
+
+        if self.id_length.is_some() {
+            config.ids.length = self.id_length;
+        }
+        if self.id_charset.is_some() {
+            config.ids.charset = self.id_charset;
+        }
+        if self.no_requested_ids {
+            config.ids.allow_requested = Some(false);
+        }
+
+        if self.token.is_some() {
+            config.auth.token = self.token;
+        }
+        config.auth.authorized_keys.extend(self.authorized_keys);
+
+        if let Some(secret) = self.turn_secret {
+            config.turn = Some(TurnConfig {
+                secret,
+                urls: self.turn_urls,
+                ttl: self.turn_ttl,
+            });
+        } else if let Some(turn) = &mut config.turn {
+            if !self.turn_urls.is_empty() {
+                turn.urls = self.turn_urls;
+            }
+            if self.turn_ttl.is_some() {
+                turn.ttl = self.turn_ttl;
+            }
+        }
+
+        if let Some(address) = self.admin_address {
+            let token = config.admin.take().and_then(|admin| admin.token);
+            config.admin = Some(AdminConfig { address, token });
+        }
+        if let (Some(admin), Some(token)) = (&mut config.admin, self.admin_token) {
+            admin.token = Some(token);
+        }
+
+        if let Some(format) = self.log_format {
+            config.log.format = format;
+        }
+        if self.log_level.is_some() {
+            config.log.level = self.log_level;
+        }
+        if self.audit_log.is_some() {
+            config.log.audit = self.audit_log;
+        }
+    }
 }
 
 #[tokio::main]
-pub async fn main() {
+pub async fn main() -> eyre::Result<()> {
     let args = Args::parse();
 
-    println!("starting signal {}", args.address);
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    args.apply(&mut config);
+
+    init_logging(&std::mem::take(&mut config.log))?;
+
+    let (addresses, options) = config.validate()?;
+
+    tracing::info!(?addresses, "starting signal");
+
+    signal::server_with_shutdown(&addresses, options, shutdown()).await
+}
+
+/// Completes on ctrl-c, or SIGTERM where there is such a thing.
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        use futures::FutureExt;
+
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).ok();
+        let terminate = async {
+            match &mut sigterm {
+                Some(sigterm) => {
+                    sigterm.recv().await;
+                }
+                None => futures::future::pending().await,
+            }
+        };
+
+        futures::select! {
+            _ = tokio::signal::ctrl_c().fuse() => {}
+            _ = terminate.fuse() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+fn init_logging(log: &LogConfig) -> eyre::Result<()> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .try_from_env()
+        .or_else(|_| {
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .parse(log.level.as_deref().unwrap_or_default())
+        })
+        .map_err(|err| eyre::eyre!("invalid log level: {err}"))?;
+
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = vec![match log.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .pretty()
+            .with_filter(filter)
+            .boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_filter(filter)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_filter(filter)
+            .boxed(),
+    }];
+
+    if let Some(path) = &log.audit {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| eyre::eyre!("unable to open audit log {}: {err}", path.display()))?;
+
+        // NOTE(emily): Audit events carry everything they need as fields, so leave the spans off.
+        layers.push(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(false)
+                .with_span_list(false)
+                .with_ansi(false)
+                .with_writer(std::sync::Mutex::new(file))
+                .with_filter(Targets::new().with_target(signal::AUDIT_TARGET, LevelFilter::INFO))
+                .boxed(),
+        );
+    }
+
+    tracing_subscriber::registry().with(layers).init();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_config() {
+        let mut config: Config = toml::from_str(
+            r#"
+            listen = ["0.0.0.0:8080"]
+            request_ttl = 20
+            ping_interval = 10
+
+            [ids]
+            length = 6
+
+            [auth]
+            token = "from-config"
+            authorized_keys = ["aa"]
+
+            [turn]
+            secret = "shared"
+            urls = ["turn:one.example.com"]
+            ttl = 600
+
+            [admin]
+            address = "127.0.0.1:9090"
+            token = "admin-from-config"
+            "#,
+        )
+        .unwrap();
+
+        Args::parse_from([
+            "signal",
+            "0.0.0.0:9000",
+            "--request-ttl",
+            "30",
+            "--id-length",
+            "8",
+            "--no-requested-ids",
+            "--token",
+            "from-flags",
+            "--authorized-key",
+            "bb",
+            "--turn-url",
+            "turn:two.example.com",
+            "--admin-address",
+            "127.0.0.1:9191",
+            "--log-format",
+            "json",
+        ])
+        .apply(&mut config);
+
+        assert_eq!(config.listen, ["0.0.0.0:8080", "0.0.0.0:9000"]);
+        assert_eq!(config.request_ttl, Some(30));
+        assert_eq!(config.ping_interval, Some(10));
+        assert_eq!(config.ids.length, Some(8));
+        assert_eq!(config.ids.allow_requested, Some(false));
+        assert_eq!(config.auth.token.as_deref(), Some("from-flags"));
+        assert_eq!(config.auth.authorized_keys, ["aa", "bb"]);
+
+        // NOTE(emily): Flags only replace the parts of a section that they set.
+        let turn = config.turn.unwrap();
+        assert_eq!(turn.secret, "shared");
+        assert_eq!(turn.urls, ["turn:two.example.com"]);
+        assert_eq!(turn.ttl, Some(600));
+        let admin = config.admin.unwrap();
+        assert_eq!(admin.address, "127.0.0.1:9191");
+        assert_eq!(admin.token.as_deref(), Some("admin-from-config"));
+        assert!(matches!(config.log.format, LogFormat::Json));
+    }
+
+    #[test]
+    fn test_no_flags_leave_config_alone() {
+        let mut config: Config = toml::from_str("request_ttl = 20").unwrap();
+        Args::parse_from(["signal"]).apply(&mut config);
 
-    signal::server(&args.address).await.unwrap();
+        assert!(config.listen.is_empty());
+        assert_eq!(config.request_ttl, Some(20));
+        assert!(config.admin.is_none());
+        assert!(config.turn.is_none());
+    }
 }
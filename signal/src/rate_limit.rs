@@ -0,0 +1,130 @@
+use tokio::time::Instant;
+
+use crate::PeerToServer;
+
+/// A token bucket, `burst` messages can be sent straight away and then `per_second` after that.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub const fn new(per_second: f64, burst: u32) -> Self {
+        Self { per_second, burst }
+    }
+}
+
+/// How fast each peer can send each kind of message.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    /// Including asking to join a room.
+    pub connection_requests: RateLimit,
+    pub ice_candidates: RateLimit,
+    /// Offers and answers.
+    pub descriptions: RateLimit,
+    /// Everything else.
+    pub other: RateLimit,
+    /// Disconnect a peer once it has gone over its limits this many times, if unset they only
+    /// get an error back.
+    pub disconnect_after: Option<u32>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            connection_requests: RateLimit::new(1.0, 5),
+            ice_candidates: RateLimit::new(20.0, 100),
+            descriptions: RateLimit::new(2.0, 10),
+            other: RateLimit::new(10.0, 20),
+            disconnect_after: Some(50),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The buckets for a single connection.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    connection_requests: Bucket,
+    ice_candidates: Bucket,
+    descriptions: Bucket,
+    other: Bucket,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: &RateLimits) -> Self {
+        Self {
+            connection_requests: Bucket::new(limits.connection_requests),
+            ice_candidates: Bucket::new(limits.ice_candidates),
+            descriptions: Bucket::new(limits.descriptions),
+            other: Bucket::new(limits.other),
+        }
+    }
+
+    /// Whether `message` is allowed through, using up some of the peer's allowance if so.
+    pub(crate) fn allow(&mut self, message: &PeerToServer) -> bool {
+        match message {
+            PeerToServer::ConnectToPeer(..) | PeerToServer::JoinRoom(_) => {
+                self.connection_requests.take()
+            }
+            PeerToServer::IceCandidate(..) => self.ice_candidates.take(),
+            PeerToServer::Offer(..) | PeerToServer::Answer(..) => self.descriptions.take(),
+            _ => self.other.take(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bucket_refills() {
+        let mut bucket = Bucket::new(RateLimit::new(2.0, 3));
+
+        assert!((0..3).all(|_| bucket.take()));
+        assert!(!bucket.take());
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(bucket.take());
+        assert!(!bucket.take());
+
+        // NOTE(emily): Never refills past the burst.
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!((0..3).all(|_| bucket.take()));
+        assert!(!bucket.take());
+    }
+}
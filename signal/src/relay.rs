@@ -0,0 +1,186 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::admin::Relayed;
+use crate::{ConnectionId, PeerId, ServerState, ServerToPeer, SignallingError};
+
+/// Offers and answers bigger than this are refused, real ones are a few KiB.
+const MAX_DESCRIPTION_LEN: usize = 32 * 1024;
+const MAX_CANDIDATE_LEN: usize = 1024;
+
+/// For each peer, the peers that they agreed to connect with and the connection that they agreed
+/// to. Offers, answers and candidates are only relayed between these.
+pub(crate) type ConnectedMap = Arc<Mutex<HashMap<PeerId, HashMap<PeerId, ConnectionId>>>>;
+
+/// Let `a` and `b` send each other offers, answers and candidates.
+pub(crate) async fn connect(
+    state: &ServerState,
+    a: &PeerId,
+    b: &PeerId,
+    connection_id: ConnectionId,
+) {
+    let mut connected = state.connected.lock().await;
+    connected
+        .entry(a.clone())
+        .or_default()
+        .insert(b.clone(), connection_id);
+    connected
+        .entry(b.clone())
+        .or_default()
+        .insert(a.clone(), connection_id);
+}
+
+fn forget(connected: &mut HashMap<PeerId, HashMap<PeerId, ConnectionId>>, a: &PeerId, b: &PeerId) {
+    if let Some(others) = connected.get_mut(a) {
+        others.remove(b);
+        if others.is_empty() {
+            connected.remove(a);
+        }
+    }
+}
+
+pub(crate) async fn disconnect(state: &ServerState, a: &PeerId, b: &PeerId) {
+    let mut connected = state.connected.lock().await;
+    forget(&mut connected, a, b);
+    forget(&mut connected, b, a);
+}
+
+/// Forget every connection that a peer that has gone for good was part of.
+pub(crate) async fn disconnect_all(state: &ServerState, peer_id: &PeerId) {
+    let mut connected = state.connected.lock().await;
+    for other in connected
+        .remove(peer_id)
+        .into_iter()
+        .flat_map(HashMap::into_keys)
+    {
+        forget(&mut connected, &other, peer_id);
+    }
+}
+
+/// Pass `inner` on from `from` to `to`, as long as they agreed to connect.
+pub(crate) async fn relay(
+    state: &ServerState,
+    from: &PeerId,
+    to: &PeerId,
+    inner: ServerToPeer,
+    relayed: Relayed,
+) -> Result<(), SignallingError> {
+    let tx = state
+        .peers
+        .lock()
+        .await
+        .get(to)
+        .map(|peer| peer.tx.clone())
+        .ok_or_else(|| SignallingError::NoSuchPeer(to.clone()))?;
+
+    if !state
+        .connected
+        .lock()
+        .await
+        .get(from)
+        .is_some_and(|others| others.contains_key(to))
+    {
+        tracing::debug!(%to, ?relayed, "refusing to relay to a peer that never accepted us");
+        state
+            .metrics
+            .refused_relays
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return Err(SignallingError::NotConnected(to.clone()));
+    }
+
+    crate::try_send(state, to, &tx, inner)?;
+    state.metrics.relayed(relayed);
+
+    Ok(())
+}
+
+/// Check that an offer or answer looks like SDP. Depending on the WebRTC implementation it is
+/// either plain SDP or JSON with the SDP in `sdp`.
+pub(crate) fn check_description(description: &str) -> Result<(), SignallingError> {
+    #[derive(Deserialize)]
+    struct Description<'a> {
+        #[serde(borrow)]
+        sdp: Cow<'a, str>,
+    }
+
+    if description.len() > MAX_DESCRIPTION_LEN {
+        return Err(SignallingError::InvalidPayload);
+    }
+
+    let sdp = if description.starts_with('{') {
+        serde_json::from_str::<Description>(description)
+            .map_err(|_err| SignallingError::InvalidPayload)?
+            .sdp
+    } else {
+        Cow::Borrowed(description)
+    };
+
+    // NOTE(emily): Every line is `<type>=<value>` with a one letter type, starting with the
+    // version (RFC 8866).
+    let valid = sdp.starts_with("v=0")
+        && sdp.lines().filter(|line| !line.is_empty()).all(|line| {
+            let line = line.as_bytes();
+            line.len() >= 2 && line[0].is_ascii_lowercase() && line[1] == b'='
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(SignallingError::InvalidPayload)
+    }
+}
+
+/// Check that an ICE candidate looks like one, with or without the `a=` that it has in SDP.
+pub(crate) fn check_candidate(candidate: &str) -> Result<(), SignallingError> {
+    // NOTE(emily): Empty means that there are no more candidates.
+    if candidate.is_empty() {
+        return Ok(());
+    }
+
+    let candidate = candidate.strip_prefix("a=").unwrap_or(candidate);
+
+    // NOTE(emily): foundation, component, transport, priority, address, port, `typ` and type at
+    // the very least (RFC 8839).
+    let valid = candidate.len() <= MAX_CANDIDATE_LEN
+        && candidate.starts_with("candidate:")
+        && !candidate.contains(['\r', '\n'])
+        && candidate.split_ascii_whitespace().count() >= 8;
+
+    if valid {
+        Ok(())
+    } else {
+        Err(SignallingError::InvalidPayload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_checks() {
+        let sdp = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0\r\n";
+        assert!(check_description(sdp).is_ok());
+        assert!(
+            check_description(&serde_json::json!({ "type": "offer", "sdp": sdp }).to_string())
+                .is_ok()
+        );
+        assert!(check_description("").is_err());
+        assert!(check_description("hello").is_err());
+        assert!(check_description("v=0\r\nnot sdp\r\n").is_err());
+        assert!(check_description(r#"{"type":"offer"}"#).is_err());
+        assert!(check_description(&format!("{sdp}{}", "a=x\r\n".repeat(10_000))).is_err());
+
+        let candidate = "candidate:1 1 udp 2130706431 192.168.1.2 50000 typ host";
+        assert!(check_candidate(candidate).is_ok());
+        assert!(check_candidate(&format!("a={candidate}")).is_ok());
+        assert!(check_candidate("").is_ok());
+        assert!(check_candidate("candidate:1 1 udp").is_err());
+        assert!(check_candidate(&format!("{candidate}\r\na=evil")).is_err());
+        assert!(check_candidate(&format!("{candidate} {}", "x".repeat(2000))).is_err());
+    }
+}
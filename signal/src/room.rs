@@ -0,0 +1,308 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    relay, try_send_to_peer, ConnectionId, ConnectionResponse, PeerId, PeerInfo,
+    RequestClosedReason, ServerState, ServerToPeer, SignallingError, AUDIT_TARGET,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct RoomId(String);
+
+impl std::fmt::Display for RoomId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for RoomId {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+/// Who gets to join a room, decided by its host.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JoinPolicy {
+    /// Anyone with the room id.
+    #[default]
+    AutoAccept,
+    /// The host approves (or rejects) each join like a connection request.
+    Approve,
+    /// Only peers that the host invited.
+    InviteOnly,
+}
+
+/// A host and the viewers that it streams to. Every member connects to the host (and not to each
+/// other), as if they had asked the host for a connection and it had accepted.
+#[derive(Debug)]
+pub(crate) struct Room {
+    pub(crate) host: PeerId,
+    pub(crate) policy: JoinPolicy,
+    pub(crate) members: HashSet<PeerId>,
+    pub(crate) invited: HashSet<PeerId>,
+}
+
+pub(crate) type RoomMap = Arc<Mutex<HashMap<RoomId, Room>>>;
+
+/// Whether a peer asking to join a room gets in, given the room's policy.
+pub(crate) enum Join {
+    /// Let them straight in.
+    Admit,
+    /// Ask the host, who answers with a connection request.
+    AskHost(PeerId),
+}
+
+pub(crate) async fn create_room(state: &ServerState, host: &PeerId, policy: JoinPolicy) -> RoomId {
+    let mut rooms = state.rooms.lock().await;
+
+    let room_id = loop {
+        let room_id = RoomId(crate::make_id(&state.options.ids).0);
+        if !rooms.contains_key(&room_id) {
+            break room_id;
+        }
+    };
+
+    rooms.insert(
+        room_id.clone(),
+        Room {
+            host: host.clone(),
+            policy,
+            members: Default::default(),
+            invited: Default::default(),
+        },
+    );
+
+    tracing::info!(target: AUDIT_TARGET, %room_id, %host, ?policy, "room created");
+
+    room_id
+}
+
+/// Work out whether `peer_id` can join `room_id`, without changing anything.
+pub(crate) async fn check_join(
+    state: &ServerState,
+    room_id: &RoomId,
+    peer_id: &PeerId,
+) -> Result<Join, SignallingError> {
+    let rooms = state.rooms.lock().await;
+    let room = rooms
+        .get(room_id)
+        .ok_or_else(|| SignallingError::NoSuchRoom(room_id.clone()))?;
+
+    if &room.host == peer_id {
+        return Err(SignallingError::CannotConnectToSelf);
+    }
+
+    match room.policy {
+        _ if room.invited.contains(peer_id) => Ok(Join::Admit),
+        JoinPolicy::AutoAccept => Ok(Join::Admit),
+        JoinPolicy::Approve => Ok(Join::AskHost(room.host.clone())),
+        JoinPolicy::InviteOnly => Err(SignallingError::NotInvited),
+    }
+}
+
+/// Let `peer_id` into the room (connecting them to the host as `connection_id`) and tell everyone
+/// already there. Returns what to tell `peer_id`, it is up to the caller to send it to them, unless
+/// they were already in the room.
+pub(crate) async fn admit(
+    state: &ServerState,
+    room_id: &RoomId,
+    peer_id: &PeerId,
+    connection_id: ConnectionId,
+) -> Result<Option<ServerToPeer>, SignallingError> {
+    let metadata = state
+        .peers
+        .lock()
+        .await
+        .get(peer_id)
+        .map(|peer| peer.metadata.clone())
+        .ok_or_else(|| SignallingError::NoSuchPeer(peer_id.clone()))?;
+
+    let (host, others) = {
+        let mut rooms = state.rooms.lock().await;
+        let room = rooms
+            .get_mut(room_id)
+            .ok_or_else(|| SignallingError::NoSuchRoom(room_id.clone()))?;
+
+        room.invited.remove(peer_id);
+        if !room.members.insert(peer_id.clone()) {
+            // NOTE(emily): Already in, nobody needs telling again.
+            return Ok(None);
+        }
+
+        let others: Vec<PeerId> = std::iter::once(room.host.clone())
+            .chain(
+                room.members
+                    .iter()
+                    .filter(|member| *member != peer_id)
+                    .cloned(),
+            )
+            .collect();
+
+        (room.host.clone(), others)
+    };
+
+    tracing::info!(target: AUDIT_TARGET, %room_id, %peer_id, %host, %connection_id, "room joined");
+
+    relay::connect(state, &host, peer_id, connection_id).await;
+
+    // NOTE(emily): Everyone else hears first, the new member starts connecting to the host as soon
+    // as they find out that they are in and the host needs to know who they are by then.
+    for other in others {
+        let _ = try_send_to_peer(
+            state,
+            &other,
+            ServerToPeer::RoomMemberJoined(
+                room_id.clone(),
+                PeerInfo {
+                    peer_id: peer_id.clone(),
+                    metadata: metadata.clone(),
+                },
+            ),
+        )
+        .await;
+    }
+
+    Ok(Some(ServerToPeer::RoomJoined(room_id.clone(), host)))
+}
+
+/// Take `peer_id` out of the room, closing it if they were the host.
+pub(crate) async fn leave(
+    state: &ServerState,
+    room_id: &RoomId,
+    peer_id: &PeerId,
+) -> Result<(), SignallingError> {
+    let (closed, host, notify) = {
+        let mut rooms = state.rooms.lock().await;
+        let room = rooms
+            .get_mut(room_id)
+            .ok_or_else(|| SignallingError::NoSuchRoom(room_id.clone()))?;
+
+        if &room.host == peer_id {
+            let room = rooms.remove(room_id).unwrap();
+            (
+                true,
+                room.host,
+                room.members.into_iter().collect::<Vec<_>>(),
+            )
+        } else if room.members.remove(peer_id) {
+            let notify: Vec<PeerId> = std::iter::once(room.host.clone())
+                .chain(room.members.iter().cloned())
+                .collect();
+            (false, room.host.clone(), notify)
+        } else {
+            return Ok(());
+        }
+    };
+
+    if closed {
+        tracing::info!(target: AUDIT_TARGET, %room_id, host = %peer_id, "room closed");
+
+        // NOTE(emily): Nobody is going to be let into it now.
+        let mut connection_requests = state.connection_requests.lock().await;
+        let connection_ids: Vec<_> = connection_requests
+            .iter()
+            .filter(|(_, request)| request.room.as_ref() == Some(room_id))
+            .map(|(connection_id, _)| *connection_id)
+            .collect();
+        for connection_id in connection_ids {
+            if let Some(request) = connection_requests.remove(&connection_id) {
+                let _ = request
+                    .response
+                    .send(ConnectionResponse::Close(RequestClosedReason::Cancelled));
+            }
+        }
+
+        for member in &notify {
+            relay::disconnect(state, &host, member).await;
+        }
+    } else {
+        tracing::info!(target: AUDIT_TARGET, %room_id, %peer_id, "room left");
+
+        relay::disconnect(state, &host, peer_id).await;
+    }
+
+    for other in notify {
+        let inner = if closed {
+            ServerToPeer::RoomClosed(room_id.clone())
+        } else {
+            ServerToPeer::RoomMemberLeft(room_id.clone(), peer_id.clone())
+        };
+        let _ = try_send_to_peer(state, &other, inner).await;
+    }
+
+    Ok(())
+}
+
+/// Take a peer that has gone for good out of every room that they were in.
+pub(crate) async fn leave_all(state: &ServerState, peer_id: &PeerId) {
+    let room_ids: Vec<RoomId> = state
+        .rooms
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, room)| &room.host == peer_id || room.members.contains(peer_id))
+        .map(|(room_id, _)| room_id.clone())
+        .collect();
+
+    for room_id in room_ids {
+        let _ = leave(state, &room_id, peer_id).await;
+    }
+}
+
+/// Only the host gets to change a room.
+async fn with_hosted_room<T>(
+    state: &ServerState,
+    room_id: &RoomId,
+    host: &PeerId,
+    f: impl FnOnce(&mut Room) -> T,
+) -> Result<T, SignallingError> {
+    let mut rooms = state.rooms.lock().await;
+    let room = rooms
+        .get_mut(room_id)
+        .ok_or_else(|| SignallingError::NoSuchRoom(room_id.clone()))?;
+
+    if &room.host != host {
+        return Err(SignallingError::NotRoomHost);
+    }
+
+    Ok(f(room))
+}
+
+pub(crate) async fn invite(
+    state: &ServerState,
+    room_id: &RoomId,
+    host: &PeerId,
+    peer_id: &PeerId,
+) -> Result<(), SignallingError> {
+    if !state.peers.lock().await.contains_key(peer_id) {
+        return Err(SignallingError::NoSuchPeer(peer_id.clone()));
+    }
+
+    with_hosted_room(state, room_id, host, |room| {
+        room.invited.insert(peer_id.clone())
+    })
+    .await?;
+
+    tracing::info!(target: AUDIT_TARGET, %room_id, %host, %peer_id, "room invitation");
+
+    try_send_to_peer(
+        state,
+        peer_id,
+        ServerToPeer::RoomInvitation(room_id.clone(), host.clone()),
+    )
+    .await
+}
+
+pub(crate) async fn set_policy(
+    state: &ServerState,
+    room_id: &RoomId,
+    host: &PeerId,
+    policy: JoinPolicy,
+) -> Result<(), SignallingError> {
+    with_hosted_room(state, room_id, host, |room| room.policy = policy).await
+}
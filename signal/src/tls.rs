@@ -0,0 +1,350 @@
+use std::{
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use eyre::{eyre, Result};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{
+    self,
+    crypto::CryptoProvider,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use tokio_rustls::TlsAcceptor;
+
+/// Terminate TLS with a PEM certificate chain and private key. Both files are read again
+/// whenever they change (or on SIGHUP), so certificates can be renewed without a restart.
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// How often to check whether the files have changed.
+    pub reload_interval: Duration,
+}
+
+impl TlsOptions {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+            reload_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The files that a [`CertifiedKey`] was loaded from, so that we can tell when they change.
+#[derive(PartialEq, Eq)]
+struct Pem {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl Pem {
+    async fn read(options: &TlsOptions) -> Result<Self> {
+        let cert = tokio::fs::read(&options.cert)
+            .await
+            .map_err(|err| eyre!("unable to read {}: {err}", options.cert.display()))?;
+        let key = tokio::fs::read(&options.key)
+            .await
+            .map_err(|err| eyre!("unable to read {}: {err}", options.key.display()))?;
+
+        Ok(Self { cert, key })
+    }
+
+    fn certified_key(&self, provider: &CryptoProvider) -> Result<CertifiedKey> {
+        let certs = rustls_pemfile::certs(&mut self.cert.as_slice())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            return Err(eyre!("no certificates found"));
+        }
+
+        let key = rustls_pemfile::private_key(&mut self.key.as_slice())?
+            .ok_or_else(|| eyre!("no private key found"))?;
+        let key = provider.key_provider.load_private_key(key)?;
+
+        let certified_key = CertifiedKey::new(certs, key);
+        certified_key.keys_match()?;
+
+        Ok(certified_key)
+    }
+}
+
+/// Hands rustls whatever certificate was loaded most recently.
+#[derive(Debug)]
+struct CertResolver(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+/// Load the certificate and start watching it for changes.
+pub(crate) async fn acceptor(options: TlsOptions) -> Result<TlsAcceptor> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let pem = Pem::read(&options).await?;
+    let certified_key = pem
+        .certified_key(&provider)
+        .map_err(|err| eyre!("invalid certificate {}: {err}", options.cert.display()))?;
+
+    let resolver = Arc::new(CertResolver(RwLock::new(Arc::new(certified_key))));
+
+    let mut config = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    tokio::spawn(reload_task(
+        options,
+        provider,
+        Arc::downgrade(&resolver),
+        pem,
+    ));
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(unix)]
+async fn hangup(signal: &mut Option<tokio::signal::unix::Signal>) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+async fn reload_task(
+    options: TlsOptions,
+    provider: Arc<CryptoProvider>,
+    resolver: std::sync::Weak<CertResolver>,
+    mut pem: Pem,
+) {
+    use futures::FutureExt;
+
+    #[cfg(unix)]
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok();
+
+    let mut interval = tokio::time::interval(options.reload_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval.tick().await;
+
+    loop {
+        #[cfg(unix)]
+        let forced = futures::select! {
+            _ = interval.tick().fuse() => false,
+            _ = hangup(&mut sighup).fuse() => true,
+        };
+        #[cfg(not(unix))]
+        let forced = {
+            interval.tick().await;
+            false
+        };
+
+        // NOTE(emily): The server has gone away, so nothing needs the certificate anymore.
+        let Some(resolver) = resolver.upgrade() else {
+            return;
+        };
+
+        let new_pem = match Pem::read(&options).await {
+            Ok(new_pem) => new_pem,
+            Err(err) => {
+                tracing::warn!(%err, "tls reload failed, keeping the old certificate");
+                continue;
+            }
+        };
+
+        if !forced && new_pem == pem {
+            continue;
+        }
+
+        match new_pem.certified_key(&provider) {
+            Ok(certified_key) => {
+                tracing::info!(cert = %options.cert.display(), "tls reloaded");
+                *resolver.0.write().unwrap() = Arc::new(certified_key);
+                pem = new_pem;
+            }
+            Err(err) => {
+                tracing::warn!(%err, "tls reload failed, keeping the old certificate");
+                // NOTE(emily): Don't keep trying the same broken files every interval.
+                pem = new_pem;
+            }
+        }
+    }
+}
+
+/// A connection to a peer, which may or may not have had TLS terminated by us.
+pub(crate) enum PeerStream {
+    Plain(TcpStream),
+    Tls(Box<tokio_rustls::server::TlsStream<TcpStream>>),
+    /// A peer in the same process, see [`crate::InProcessBroker`].
+    Memory(DuplexStream),
+}
+
+impl AsyncRead for PeerStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Memory(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for PeerStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Memory(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Memory(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Memory(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{SinkExt, StreamExt};
+    use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+    use tokio_tungstenite::tungstenite::Message;
+
+    use crate::{
+        ClientOptions, PeerToServer, PeerToServerMessage, ServerOptions, PROTOCOL_VERSION,
+    };
+
+    fn self_signed() -> (String, String, CertificateDer<'static>) {
+        let rcgen::CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        (cert.pem(), signing_key.serialize_pem(), cert.der().clone())
+    }
+
+    /// Do the websocket handshake and a hello over TLS, trusting only `root`.
+    async fn connect(address: std::net::SocketAddr, root: CertificateDer<'static>) -> Result<()> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(root)?;
+
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        let stream = TcpStream::connect(address).await?;
+        let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost")?, stream)
+            .await?;
+
+        let (mut ws_stream, _) =
+            tokio_tungstenite::client_async(format!("wss://localhost:{}", address.port()), stream)
+                .await?;
+
+        ws_stream
+            .send(Message::text(serde_json::to_string(
+                &PeerToServerMessage {
+                    job_id: 0,
+                    inner: PeerToServer::Hello {
+                        protocol_version: PROTOCOL_VERSION,
+                        features: vec![],
+                    },
+                },
+            )?))
+            .await?;
+
+        match ws_stream.next().await {
+            Some(Ok(Message::Text(text))) if text.contains("Hello") => Ok(()),
+            other => Err(eyre!("expected hello, got {other:?}")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_reloads_changed_certificate() {
+        let dir = std::env::temp_dir().join(format!("signal-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (cert_pem, key_pem, first) = self_signed();
+        std::fs::write(dir.join("cert.pem"), cert_pem).unwrap();
+        std::fs::write(dir.join("key.pem"), key_pem).unwrap();
+
+        let mut tls = TlsOptions::new(dir.join("cert.pem"), dir.join("key.pem"));
+        tls.reload_interval = Duration::from_millis(50);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(crate::serve(
+            vec![listener],
+            None,
+            ServerOptions {
+                tls: Some(tls),
+                ..Default::default()
+            },
+            std::future::pending(),
+        ));
+
+        connect(address, first.clone()).await.unwrap();
+
+        // NOTE(emily): Our own client trusts the certificate only when told to.
+        let server = format!("wss://localhost:{}", address.port());
+        let trusting = |root: &CertificateDer<'static>| ClientOptions {
+            tls_roots: vec![root.clone()],
+            ..Default::default()
+        };
+        let (_control, mut event_rx, _features) =
+            crate::client(&server, trusting(&first)).await.unwrap();
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(crate::SignallingEvent::Id(_))
+        ));
+        assert!(crate::client(&server, Default::default()).await.is_err());
+
+        let (cert_pem, key_pem, second) = self_signed();
+        std::fs::write(dir.join("key.pem"), key_pem).unwrap();
+        std::fs::write(dir.join("cert.pem"), cert_pem).unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        connect(address, second.clone()).await.unwrap();
+        assert!(connect(address, first.clone()).await.is_err());
+        assert!(crate::client(&server, trusting(&second)).await.is_ok());
+        assert!(crate::client(&server, trusting(&first)).await.is_err());
+
+        // NOTE(emily): A broken certificate leaves the last good one in place.
+        std::fs::write(dir.join("cert.pem"), "garbage").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        connect(address, second).await.unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,603 @@
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use base64::Engine;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::Instrument;
+
+use crate::tls::PeerStream;
+use crate::{
+    ClientOptions, Features, IdPolicy, PeerInfo, RejectionReason, RequestClosedReason,
+    ServerOptions, ServerState, ServerToPeer, SignallingControl, SignallingError, SignallingEvent,
+    SignallingRequest, SignallingResponse, ARBITRARY_SIGNALLING_CHANNEL_LIMIT,
+};
+
+/// A way to control a signalling connection, the events that come from it and the features that
+/// it supports, see [`crate::client`].
+pub type Signalling = (
+    mpsc::Sender<SignallingControl>,
+    mpsc::Receiver<SignallingEvent>,
+    Features,
+);
+
+/// Somewhere that peers find each other and swap their offers, answers and candidates.
+#[async_trait::async_trait]
+pub trait SignallingTransport: Send + Sync {
+    async fn connect(&self, options: ClientOptions) -> Result<Signalling>;
+}
+
+/// A signal server at the other end of a websocket.
+#[derive(Debug, Clone)]
+pub struct WebsocketTransport {
+    pub address: String,
+}
+
+impl WebsocketTransport {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SignallingTransport for WebsocketTransport {
+    async fn connect(&self, options: ClientOptions) -> Result<Signalling> {
+        crate::client(&self.address, options).await
+    }
+}
+
+/// A signal server that lives in this process, for tests and for peers on the same machine.
+/// Peers still speak the websocket protocol to it (just over memory instead of a socket), so
+/// everything behaves as it would with a real server. `tls` and `admin` are ignored.
+#[derive(Clone)]
+pub struct InProcessBroker {
+    state: ServerState,
+}
+
+impl InProcessBroker {
+    pub fn new(options: ServerOptions) -> Self {
+        Self {
+            state: ServerState::new(options),
+        }
+    }
+}
+
+impl Default for InProcessBroker {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+#[async_trait::async_trait]
+impl SignallingTransport for InProcessBroker {
+    async fn connect(&self, options: ClientOptions) -> Result<Signalling> {
+        crate::client_with(Connector::InProcess(self.state.clone()), options).await
+    }
+}
+
+/// What peers connected to an [`InProcessBroker`] show up as, they have no real address.
+const IN_PROCESS_ADDRESS: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+/// How much can be written to an in-process connection before the writer has to wait.
+const IN_PROCESS_BUFFER: usize = 64 * 1024;
+
+/// Where the client gets a websocket to the server from, again whenever it reconnects.
+#[derive(Clone)]
+pub(crate) enum Connector {
+    Address(String),
+    InProcess(ServerState),
+}
+
+impl Connector {
+    pub(crate) async fn open(
+        &self,
+        options: &ClientOptions,
+    ) -> Result<WebSocketStream<MaybeTlsStream<ClientStream>>> {
+        match self {
+            Self::Address(address) => {
+                let request = address.as_str().into_client_request()?;
+                let uri = request.uri();
+                let host = uri
+                    .host()
+                    .ok_or_else(|| eyre!("no host in {address}"))?
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .to_owned();
+                let tls = uri.scheme_str() == Some("wss");
+                let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+
+                let stream = TcpStream::connect((host.as_str(), port)).await?;
+                let (ws_stream, _) = tokio_tungstenite::client_async_tls_with_config(
+                    request,
+                    ClientStream::Tcp(stream),
+                    None,
+                    tls.then(|| tls_connector(&options.tls_roots)).transpose()?,
+                )
+                .await?;
+                Ok(ws_stream)
+            }
+            Self::InProcess(state) => {
+                let (client, server) = tokio::io::duplex(IN_PROCESS_BUFFER);
+
+                let span = tracing::info_span!(
+                    "peer",
+                    addr = %IN_PROCESS_ADDRESS,
+                    peer_id = tracing::field::Empty
+                );
+                tokio::spawn(
+                    crate::handle_peer(
+                        PeerStream::Memory(server),
+                        IN_PROCESS_ADDRESS,
+                        state.clone(),
+                    )
+                    .instrument(span),
+                );
+
+                let (ws_stream, _) = tokio_tungstenite::client_async(
+                    "ws://in-process/",
+                    MaybeTlsStream::Plain(ClientStream::Memory(client)),
+                )
+                .await?;
+                Ok(ws_stream)
+            }
+        }
+    }
+}
+
+/// Trust the usual web roots along with `extra_roots`.
+fn tls_connector(extra_roots: &[CertificateDer<'static>]) -> Result<tokio_tungstenite::Connector> {
+    let mut roots = tungstenite_rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    for root in extra_roots {
+        roots.add(root.clone())?;
+    }
+
+    let config = tungstenite_rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(tokio_tungstenite::Connector::Rustls(Arc::new(config)))
+}
+
+/// The client's end of a connection to the server.
+pub(crate) enum ClientStream {
+    Tcp(TcpStream),
+    Memory(DuplexStream),
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Memory(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Memory(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Memory(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Memory(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Everything that one side of a [`ManualTransport`] has sent so far, as the other side would
+/// have got it from a server.
+#[derive(Serialize, Deserialize)]
+struct ManualBlob {
+    peer: PeerInfo,
+    messages: Vec<ServerToPeer>,
+}
+
+#[derive(Default)]
+struct Manual {
+    us: Option<PeerInfo>,
+    them: Option<PeerInfo>,
+    sent: Vec<ServerToPeer>,
+    /// How many of the other side's messages have been pasted in already.
+    received: usize,
+    event_tx: Option<mpsc::Sender<SignallingEvent>>,
+}
+
+/// Signalling without a server, the user carries everything between the two sides themselves
+/// (over chat, email, ...). Each side hands [`ManualTransport::outgoing`] to the other, who
+/// [`ManualTransport::paste`]s it in, back and forth until the peers connect. There is only ever
+/// one other peer and no rooms or invites.
+#[derive(Clone)]
+pub struct ManualTransport {
+    state: Arc<Mutex<Manual>>,
+    outgoing: Arc<watch::Sender<String>>,
+}
+
+impl ManualTransport {
+    pub fn new() -> Self {
+        Self {
+            state: Default::default(),
+            outgoing: Arc::new(watch::channel(String::new()).0),
+        }
+    }
+
+    /// Text for the other side to paste in, it changes every time we send something. Every
+    /// version has everything in it, so only the latest needs handing over.
+    pub fn outgoing(&self) -> watch::Receiver<String> {
+        self.outgoing.subscribe()
+    }
+
+    /// Take in text that the other side handed over.
+    pub async fn paste(&self, text: &str) -> Result<()> {
+        let blob: ManualBlob = serde_json::from_slice(
+            &base64::engine::general_purpose::STANDARD.decode(text.trim())?,
+        )?;
+
+        let mut manual = self.state.lock().await;
+        let (Some(us), Some(event_tx)) = (&manual.us, manual.event_tx.clone()) else {
+            return Err(eyre!("not connected"));
+        };
+
+        if blob.peer.peer_id == us.peer_id {
+            return Err(eyre!(
+                "that is what we sent, paste what the other side sent"
+            ));
+        }
+
+        // NOTE(emily): Someone new, or the other side started over.
+        if !manual
+            .them
+            .as_ref()
+            .is_some_and(|them| them.peer_id == blob.peer.peer_id)
+            || blob.messages.len() < manual.received
+        {
+            manual.received = 0;
+            manual.them = Some(blob.peer.clone());
+            event_tx
+                .send(SignallingEvent::PeerJoined(blob.peer))
+                .await?;
+        }
+
+        let received = manual.received;
+        for inner in blob.messages.into_iter().skip(received) {
+            manual.received += 1;
+            if let Some(event) = crate::into_event(inner) {
+                event_tx.send(event).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add `inner` to what the other side gets handed.
+    fn send(&self, manual: &mut Manual, inner: ServerToPeer) {
+        manual.sent.push(inner);
+        self.publish(manual);
+    }
+
+    fn publish(&self, manual: &Manual) {
+        let Some(peer) = manual.us.clone() else {
+            return;
+        };
+
+        let blob = ManualBlob {
+            peer,
+            messages: manual.sent.clone(),
+        };
+        match serde_json::to_vec(&blob) {
+            Ok(json) => {
+                self.outgoing
+                    .send_replace(base64::engine::general_purpose::STANDARD.encode(json));
+            }
+            Err(err) => tracing::error!(%err, "unable to encode manual signalling"),
+        }
+    }
+
+    async fn handle_control(&self, control: SignallingControl) {
+        let mut manual = self.state.lock().await;
+        let (Some(us), Some(event_tx)) = (manual.us.clone(), manual.event_tx.clone()) else {
+            return;
+        };
+        let peer_id = us.peer_id;
+
+        let peers = manual.them.iter().cloned().collect::<Vec<_>>();
+
+        // NOTE(emily): There is only the other side, so who a message is for does not matter.
+        let inner = match control {
+            SignallingControl::IceCandidate(_, candidate) => {
+                ServerToPeer::IceCandidate(peer_id, candidate)
+            }
+            SignallingControl::Offer(_, offer) => ServerToPeer::Offer(peer_id, offer),
+            SignallingControl::Answer(_, answer) => ServerToPeer::Answer(peer_id, answer),
+            SignallingControl::RequestConnection(their_peer_id) => {
+                let connection_id = crate::make_connection_id();
+                self.send(
+                    &mut manual,
+                    ServerToPeer::ConnectionRequest(peer_id, connection_id),
+                );
+                let _ = event_tx
+                    .send(SignallingEvent::ConnectionRequested(
+                        their_peer_id,
+                        connection_id,
+                    ))
+                    .await;
+                return;
+            }
+            SignallingControl::Request(
+                SignallingRequest::RequestConnection(their_peer_id, _),
+                response,
+            ) => {
+                let connection_id = crate::make_connection_id();
+                self.send(
+                    &mut manual,
+                    ServerToPeer::ConnectionRequest(peer_id, connection_id),
+                );
+                let _ = response.send(Ok(SignallingResponse::ConnectionRequested(
+                    their_peer_id,
+                    connection_id,
+                )));
+                return;
+            }
+            SignallingControl::AcceptConnection(connection_id) => {
+                ServerToPeer::ConnectionAccepted(peer_id, connection_id)
+            }
+            SignallingControl::RejectConnection(connection_id) => {
+                ServerToPeer::ConnectionRejected(peer_id, connection_id, RejectionReason::Declined)
+            }
+            SignallingControl::CancelConnection(connection_id) => {
+                ServerToPeer::ConnectionRequestClosed(
+                    peer_id,
+                    connection_id,
+                    RequestClosedReason::Cancelled,
+                )
+            }
+            SignallingControl::ListPeers => {
+                let _ = event_tx.send(SignallingEvent::Peers(peers)).await;
+                return;
+            }
+            SignallingControl::Request(SignallingRequest::ListPeers, response) => {
+                let _ = response.send(Ok(SignallingResponse::Peers(peers)));
+                return;
+            }
+            SignallingControl::Request(_, response) => {
+                let _ = response.send(Err(SignallingError::Unsupported));
+                return;
+            }
+            SignallingControl::LeaveRoom(_)
+            | SignallingControl::InviteToRoom(..)
+            | SignallingControl::SetRoomPolicy(..)
+            | SignallingControl::RevokeInvite(_) => {
+                let _ = event_tx
+                    .send(SignallingEvent::Error(SignallingError::Unsupported))
+                    .await;
+                return;
+            }
+            SignallingControl::_Pong(_) => return,
+        };
+
+        self.send(&mut manual, inner);
+    }
+}
+
+impl Default for ManualTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl SignallingTransport for ManualTransport {
+    async fn connect(&self, options: ClientOptions) -> Result<Signalling> {
+        let mut manual = self.state.lock().await;
+        if manual.us.is_some() {
+            return Err(eyre!("manual signalling only connects once"));
+        }
+
+        let peer_id = options
+            .peer_id
+            .unwrap_or_else(|| crate::make_id(&IdPolicy::default()));
+
+        let (control_tx, mut control_rx) =
+            mpsc::channel::<SignallingControl>(ARBITRARY_SIGNALLING_CHANNEL_LIMIT);
+        let (event_tx, event_rx) =
+            mpsc::channel::<SignallingEvent>(ARBITRARY_SIGNALLING_CHANNEL_LIMIT);
+
+        event_tx.send(SignallingEvent::Id(peer_id.clone())).await?;
+
+        manual.us = Some(PeerInfo {
+            peer_id,
+            metadata: options.metadata,
+        });
+        manual.event_tx = Some(event_tx);
+        self.publish(&manual);
+
+        tokio::spawn({
+            let zelf = self.clone();
+            async move {
+                while let Some(control) = control_rx.recv().await {
+                    zelf.handle_control(control).await;
+                }
+
+                // NOTE(emily): Nothing more is coming, let whoever has the events know.
+                zelf.state.lock().await.event_tx = None;
+            }
+        });
+
+        Ok((control_tx, event_rx, Features::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::tests::next_event;
+    use crate::{ConnectionId, PeerId};
+
+    async fn connect(
+        transport: &dyn SignallingTransport,
+    ) -> (
+        mpsc::Sender<SignallingControl>,
+        mpsc::Receiver<SignallingEvent>,
+        PeerId,
+    ) {
+        let (control, mut event_rx, _features) =
+            transport.connect(Default::default()).await.unwrap();
+        let SignallingEvent::Id(peer_id) = next_event(&mut event_rx).await else {
+            panic!("expected id");
+        };
+        (control, event_rx, peer_id)
+    }
+
+    #[tokio::test]
+    async fn test_in_process_broker() {
+        let broker = InProcessBroker::default();
+
+        let (host, mut host_rx, host_id) = connect(&broker).await;
+        let (viewer, mut viewer_rx, viewer_id) = connect(&broker).await;
+
+        let peers = crate::list_peers(&viewer).await.unwrap();
+        assert!(peers.iter().any(|peer| peer.peer_id == host_id));
+
+        let connection_id = crate::request_connection(&viewer, host_id.clone())
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConectionRequest(peer_id, id) if peer_id == viewer_id && id == connection_id
+        ));
+
+        host.send(SignallingControl::AcceptConnection(connection_id))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::ConnectionAccepted(peer_id, id) if peer_id == host_id && id == connection_id
+        ));
+
+        let sdp = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n";
+        viewer
+            .send(SignallingControl::Offer(host_id, sdp.to_owned()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::Offer(peer_id, offer) if peer_id == viewer_id && offer == sdp
+        ));
+
+        // NOTE(emily): Brokers are separate servers.
+        let (other, _other_rx, _) = connect(&InProcessBroker::default()).await;
+        assert!(crate::list_peers(&other).await.unwrap().is_empty());
+    }
+
+    /// Paste everything that one side has sent so far into `to`, once it has sent something new.
+    async fn hand_over(outgoing: &mut watch::Receiver<String>, to: &ManualTransport) {
+        tokio::time::timeout(Duration::from_secs(5), outgoing.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        let text = outgoing.borrow_and_update().clone();
+        to.paste(&text).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_manual_transport() {
+        let host = ManualTransport::new();
+        let viewer = ManualTransport::new();
+        let mut host_outgoing = host.outgoing();
+        let mut viewer_outgoing = viewer.outgoing();
+
+        let (host_control, mut host_rx, host_id) = connect(&host).await;
+        let (viewer_control, mut viewer_rx, viewer_id) = connect(&viewer).await;
+        assert!(host.connect(Default::default()).await.is_err());
+        viewer_outgoing.mark_unchanged();
+
+        // NOTE(emily): The viewer finds out who the host is from what the host hands over.
+        hand_over(&mut host_outgoing, &viewer).await;
+        assert!(matches!(
+            viewer_rx.recv().await,
+            Some(SignallingEvent::PeerJoined(peer)) if peer.peer_id == host_id
+        ));
+        let peers = crate::list_peers(&viewer_control).await.unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].peer_id, host_id);
+
+        let connection_id: ConnectionId =
+            crate::request_connection(&viewer_control, host_id.clone())
+                .await
+                .unwrap();
+        hand_over(&mut viewer_outgoing, &host).await;
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::ConectionRequest(peer_id, id) if peer_id == viewer_id && id == connection_id
+        ));
+
+        host_control
+            .send(SignallingControl::AcceptConnection(connection_id))
+            .await
+            .unwrap();
+        hand_over(&mut host_outgoing, &viewer).await;
+        assert!(matches!(
+            next_event(&mut viewer_rx).await,
+            SignallingEvent::ConnectionAccepted(peer_id, id) if peer_id == host_id && id == connection_id
+        ));
+
+        viewer_control
+            .send(SignallingControl::Offer(
+                host_id.clone(),
+                "offer".to_owned(),
+            ))
+            .await
+            .unwrap();
+        hand_over(&mut viewer_outgoing, &host).await;
+        assert!(matches!(
+            next_event(&mut host_rx).await,
+            SignallingEvent::Offer(peer_id, offer) if peer_id == viewer_id && offer == "offer"
+        ));
+
+        // NOTE(emily): Pasting the same thing again does not repeat anything.
+        let text = viewer_outgoing.borrow().clone();
+        host.paste(&text).await.unwrap();
+        assert!(host_rx.try_recv().is_err());
+
+        assert!(viewer.paste(&text).await.is_err());
+        assert!(matches!(
+            crate::create_room(&viewer_control, Default::default()).await,
+            Err(SignallingError::Unsupported)
+        ));
+    }
+}
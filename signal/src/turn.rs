@@ -0,0 +1,85 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::{IceServer, PeerId};
+
+/// A TURN server that shares a secret with us, so that we can hand out short lived credentials
+/// for it (the "REST API" scheme, coturn's `use-auth-secret`).
+#[derive(Clone)]
+pub struct TurnOptions {
+    /// e.g. `turn:turn.example.com:3478?transport=udp`.
+    pub urls: Vec<String>,
+    pub secret: String,
+    /// How long the credentials that we hand out are valid for.
+    pub ttl: Duration,
+}
+
+impl std::fmt::Debug for TurnOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TurnOptions")
+            .field("urls", &self.urls)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TurnOptions {
+    pub(crate) fn ice_server(&self, peer_id: &PeerId) -> IceServer {
+        let expires = (SystemTime::now() + self.ttl)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let username = format!("{expires}:{peer_id}");
+        let credential = credential(&self.secret, &username);
+
+        IceServer {
+            urls: self.urls.clone(),
+            username: Some(username),
+            credential: Some(credential),
+        }
+    }
+}
+
+fn credential(secret: &str, username: &str) -> String {
+    let mut mac =
+        Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any length");
+    mac.update(username.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credential() {
+        assert_eq!(
+            credential("north", "1700000000:abcde"),
+            "8zLXlGc98I+sU5Y6uLCWfOr4in4="
+        );
+    }
+
+    #[test]
+    fn test_ice_server_username() {
+        let turn = TurnOptions {
+            urls: vec!["turn:localhost:3478".to_owned()],
+            secret: "north".to_owned(),
+            ttl: Duration::from_secs(60),
+        };
+
+        let ice_server = turn.ice_server(&PeerId::from("abcde".to_owned()));
+        let username = ice_server.username.unwrap();
+        let (expires, peer_id) = username.split_once(':').unwrap();
+
+        assert_eq!(peer_id, "abcde");
+        assert!(expires.parse::<u64>().unwrap() > 1700000000);
+        assert_eq!(
+            ice_server.credential.unwrap(),
+            credential("north", &username)
+        );
+    }
+}
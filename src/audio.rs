@@ -1,5 +1,5 @@
-use crate::ARBITRARY_CHANNEL_LIMIT;
-use rtc::{self, ChannelControl, ChannelEvent, PeerConnection};
+use crate::{ARBITRARY_CHANNEL_LIMIT, AUDIO_CHANNEL_ID};
+use rtc::{self, ChannelControl, ChannelEvent, ChannelOptions, PeerConnection};
 use tokio::sync::mpsc;
 
 use eyre::Result;
@@ -21,7 +21,16 @@ pub(crate) async fn audio_channel(
     let (control_tx, mut control_rx) = mpsc::channel(ARBITRARY_CHANNEL_LIMIT);
     let (event_tx, event_rx) = mpsc::channel(ARBITRARY_CHANNEL_LIMIT);
 
-    let (tx, mut rx) = peer_connection.channel("audio", controlling, None).await?;
+    let (tx, mut rx) = peer_connection
+        .channel(
+            "audio",
+            controlling,
+            Some(ChannelOptions {
+                negotiated: Some(AUDIO_CHANNEL_ID),
+                ..Default::default()
+            }),
+        )
+        .await?;
 
     tokio::spawn({
         let _tx = tx.clone();
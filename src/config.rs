@@ -14,6 +14,10 @@ pub(crate) struct Config {
     pub(crate) log_level: tracing::level_filters::LevelFilter,
     pub(crate) webrtc_api: rtc::Api,
     pub(crate) signal_server: String,
+    pub(crate) signal_credentials: signal::Credentials,
+    pub(crate) signal_peer_id: Option<String>,
+    pub(crate) signal_metadata: signal::PeerMetadata,
+    pub(crate) rtc: rtc::RtcConfig,
 }
 
 static CONFIG: OnceCell<Config> = OnceCell::new();
@@ -41,8 +45,85 @@ impl Config {
                         "log_level",
                     )?)?,
                     signal_server: std::env::var("signal_server")?,
+                    signal_credentials: match (
+                        std::env::var("signal_token").ok(),
+                        std::env::var("signal_key").ok(),
+                    ) {
+                        (_, Some(key)) => {
+                            signal::Credentials::Key(signal::parse_signing_key(&key)?)
+                        }
+                        (Some(token), None) => signal::Credentials::Token(token),
+                        (None, None) => signal::Credentials::None,
+                    },
+                    signal_peer_id: std::env::var("signal_peer_id").ok(),
+                    signal_metadata: signal::PeerMetadata {
+                        display_name: std::env::var("signal_display_name").ok(),
+                        role: match std::env::var("signal_role").as_deref() {
+                            Ok("viewer") => signal::PeerRole::Viewer,
+                            Ok("host") | Err(_) => signal::PeerRole::Host,
+                            Ok(role) => return Err(eyre::eyre!("unknown signal_role {role}")),
+                        },
+                        capabilities: vec!["audio".to_owned(), "video".to_owned()],
+                    },
+                    rtc: rtc_config()?,
                 })
             })
             .unwrap()
     }
 }
+
+/// Comma separated values, empty if unset.
+fn list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn rtc_config() -> eyre::Result<rtc::RtcConfig> {
+    let mut config = rtc::RtcConfig::default();
+
+    let urls = list("ice_servers");
+    if !urls.is_empty() {
+        config.ice_servers = vec![rtc::IceServer {
+            urls,
+            username: std::env::var("ice_username").ok(),
+            credential: std::env::var("ice_credential").ok(),
+        }];
+    }
+
+    config.ice_transport_policy = match std::env::var("ice_transport_policy").as_deref() {
+        Ok("all") | Err(_) => rtc::IceTransportPolicy::All,
+        Ok("relay") => rtc::IceTransportPolicy::Relay,
+        Ok(policy) => return Err(eyre::eyre!("unknown ice_transport_policy {policy}")),
+    };
+
+    config.interfaces = list("ice_interfaces");
+    config.ips = list("ice_ips")
+        .iter()
+        .map(|ip| std::net::IpAddr::from_str(ip))
+        .collect::<Result<_, _>>()?;
+    config.nat_1to1_ips = list("ice_nat_1to1_ips")
+        .iter()
+        .map(|ip| std::net::IpAddr::from_str(ip))
+        .collect::<Result<_, _>>()?;
+
+    if let Ok(udp_ports) = std::env::var("ice_udp_ports") {
+        let (start, end) = udp_ports
+            .split_once('-')
+            .ok_or_else(|| eyre::eyre!("ice_udp_ports should look like 50000-50100"))?;
+        let (start, end) = (u16::from_str(start.trim())?, u16::from_str(end.trim())?);
+        if start > end {
+            return Err(eyre::eyre!("ice_udp_ports {udp_ports} is backwards"));
+        }
+        config.udp_ports = Some(start..=end);
+    }
+
+    Ok(config)
+}
@@ -5,9 +5,9 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tracing::Instrument;
 
-use rtc::{ChannelControl, ChannelEvent, PeerConnection};
+use rtc::{ChannelControl, ChannelEvent, ChannelOptions, PeerConnection};
 
-use crate::ARBITRARY_CHANNEL_LIMIT;
+use crate::{ARBITRARY_CHANNEL_LIMIT, LOGIC_CHANNEL_ID};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub(crate) struct Mode {
@@ -56,7 +56,16 @@ pub(crate) async fn logic_channel(
     let (control_tx, mut control_rx) = mpsc::channel(ARBITRARY_CHANNEL_LIMIT);
     let (event_tx, event_rx) = mpsc::channel(ARBITRARY_CHANNEL_LIMIT);
 
-    let (tx, mut rx) = peer_connection.channel("logic", controlling, None).await?;
+    let (tx, mut rx) = peer_connection
+        .channel(
+            "logic",
+            controlling,
+            Some(ChannelOptions {
+                negotiated: Some(LOGIC_CHANNEL_ID),
+                ..Default::default()
+            }),
+        )
+        .await?;
 
     tokio::spawn({
         let weak_control_tx = control_tx.downgrade();
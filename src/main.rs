@@ -31,9 +31,17 @@ use uuid::Uuid;
 
 const ARBITRARY_CHANNEL_LIMIT: usize = 5;
 
+// NOTE(emily): Both sides make these channels themselves with these SCTP stream ids, so they must
+// never change between versions that talk to each other.
+const LOGIC_CHANNEL_ID: u16 = 0;
+const AUDIO_CHANNEL_ID: u16 = 1;
+const VIDEO_CHANNEL_ID: u16 = 2;
+
 #[derive(Debug, Clone)]
 enum Command {
     Ui,
+    /// List the peers that are online on the signal server.
+    Peers,
 }
 
 #[derive(Debug)]
@@ -66,6 +74,7 @@ impl FromStr for Command {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "ui" => Ok(Self::Ui),
+            "peers" => Ok(Self::Peers),
             _ => Err(CommandParseError),
         }
     }
@@ -112,5 +121,36 @@ async fn main() -> Result<()> {
     let command = args.command.as_str().parse()?;
     match command {
         Command::Ui => Ok(ui::ui().await?),
+        Command::Peers => Ok(peers(config).await?),
     }
 }
+
+async fn peers(config: &Config) -> Result<()> {
+    let (control, mut event_rx, _features) = signal::client(
+        &config.signal_server,
+        signal::ClientOptions {
+            credentials: config.signal_credentials.clone(),
+            peer_id: None,
+            metadata: signal::PeerMetadata {
+                role: signal::PeerRole::Viewer,
+                ..config.signal_metadata.clone()
+            },
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    // NOTE(emily): Nobody is interested in the events, but the client stalls if they pile up.
+    tokio::spawn(async move { while event_rx.recv().await.is_some() {} });
+
+    for signal::PeerInfo { peer_id, metadata } in signal::list_peers(&control).await? {
+        println!(
+            "{peer_id}\t{:?}\t{}\t{}",
+            metadata.role,
+            metadata.display_name.unwrap_or_default(),
+            metadata.capabilities.join(","),
+        );
+    }
+
+    Ok(())
+}
@@ -4,12 +4,19 @@ use crate::rtc::{self};
 use crate::video::video_channel;
 use crate::{PeerId, ARBITRARY_CHANNEL_LIMIT};
 use media::VideoBuffer;
-use signal::SignallingControl;
+use signal::{Permission, SignallingControl};
+
+use std::sync::Arc;
 
 use eyre::Result;
 use tokio::sync::mpsc;
 use tracing::Instrument;
 
+/// How many ICE restarts to try in a row before waiting for the connection to fail.
+const MAX_ICE_RESTARTS: u32 = 3;
+/// How often connection statistics are collected.
+const STATS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
 #[derive(Debug)]
 pub(crate) enum PeerError {
     Unknown,
@@ -38,6 +45,11 @@ pub(crate) enum PeerEvent {
 
     Audio(Vec<u8>),
     Video(VideoBuffer),
+    /// Lost the connection, trying to get it back with an ICE restart.
+    Recovering,
+    /// Got the connection back.
+    Recovered,
+    Stats(rtc::RtcStats),
     Error(PeerError),
 }
 
@@ -50,6 +62,9 @@ impl std::fmt::Debug for PeerEvent {
             }
             Self::Audio(arg0) => f.debug_tuple("Audio").field(&arg0.len()).finish(),
             Self::Video(arg0) => f.debug_tuple("Video").field(arg0).finish(),
+            Self::Recovering => write!(f, "Recovering"),
+            Self::Recovered => write!(f, "Recovered"),
+            Self::Stats(arg0) => f.debug_tuple("Stats").field(arg0).finish(),
             Self::Error(arg0) => f.debug_tuple("Error").field(arg0).finish(),
         }
     }
@@ -58,12 +73,14 @@ impl std::fmt::Debug for PeerEvent {
 #[tracing::instrument(skip(api, signalling_control))]
 pub(crate) async fn peer(
     api: rtc::Api,
+    rtc_config: rtc::RtcConfig,
     our_peer_id: PeerId,
     their_peer_id: PeerId,
     signalling_control: mpsc::Sender<SignallingControl>,
     controlling: bool,
+    permission: Permission,
 ) -> Result<(mpsc::Sender<PeerControl>, mpsc::Receiver<PeerEvent>)> {
-    let (peer_connection, rtc_control, mut rtc_event) = api.peer(controlling).await?;
+    let (peer_connection, rtc_control, mut rtc_event) = api.peer(controlling, rtc_config).await?;
 
     // TODO(emily): Funnelling audio + video through the same channel here creates a pinch point that can be less ideal.
     let (control_tx, mut control_rx) = mpsc::channel(ARBITRARY_CHANNEL_LIMIT);
@@ -175,6 +192,12 @@ pub(crate) async fn peer(
                 while let Some(event) = logic_rx.recv().await {
                     match event {
                         logic::LogicMessage::StreamRequest(request) => {
+                            // NOTE(emily): Peers that are only allowed to watch get whatever we
+                            // choose to send them.
+                            let request = match permission {
+                                Permission::ViewOnly => logic::PeerStreamRequest::default(),
+                                Permission::Control => request,
+                            };
                             event_tx.send(PeerEvent::StreamRequest(request)).await?;
                         }
                         logic::LogicMessage::StreamRequestResponse(response) => {
@@ -238,8 +261,11 @@ pub(crate) async fn peer(
         let their_peer_id = their_peer_id.clone();
         let signalling_control = signalling_control.clone();
         let event_tx = event_tx.downgrade();
+        let rtc_control = rtc_control.downgrade();
         async move {
             match async move {
+                let mut ice_restarts = 0;
+
                 while let Some(event) = rtc_event.recv().await {
                     match event {
                         rtc::RtcPeerEvent::IceCandidate(candidate) => {
@@ -260,6 +286,32 @@ pub(crate) async fn peer(
                                 }
                                 break;
                             }
+
+                            if let rtc::RtcPeerState::Disconnected = state_change {
+                                if let Some(event_tx) = event_tx.upgrade() {
+                                    event_tx.send(PeerEvent::Recovering).await?;
+                                }
+
+                                // NOTE(emily): Only the controlling side restarts so that the two
+                                // sides never make offers at the same time, the other side answers.
+                                if controlling && ice_restarts < MAX_ICE_RESTARTS {
+                                    ice_restarts += 1;
+                                    tracing::info!(ice_restarts, "disconnected, restarting ice");
+                                    if let Some(rtc_control) = rtc_control.upgrade() {
+                                        rtc_control.send(rtc::RtcPeerControl::RestartIce).await?;
+                                    }
+                                }
+                            }
+                        }
+                        rtc::RtcPeerEvent::IceRestarting => {
+                            tracing::debug!("ice restart offered");
+                        }
+                        rtc::RtcPeerEvent::Recovered => {
+                            tracing::info!("peer connection recovered");
+                            ice_restarts = 0;
+                            if let Some(event_tx) = event_tx.upgrade() {
+                                event_tx.send(PeerEvent::Recovered).await?;
+                            }
                         }
                         rtc::RtcPeerEvent::Offer(offer) => {
                             signalling_control
@@ -287,6 +339,63 @@ pub(crate) async fn peer(
         .in_current_span()
     });
 
+    tokio::spawn({
+        let peer_connection = Arc::downgrade(&peer_connection);
+        let event_tx = event_tx.downgrade();
+        let span = tracing::span!(tracing::Level::DEBUG, "Stats", %our_peer_id, %their_peer_id);
+        async move {
+            let sent_counter = telemetry::client::Counter::default();
+            let recv_counter = telemetry::client::Counter::default();
+            telemetry::client::watch_counter(
+                &sent_counter,
+                telemetry::Unit::Bytes,
+                &format!("peer-{their_peer_id}-sent"),
+            )
+            .await;
+            telemetry::client::watch_counter(
+                &recv_counter,
+                telemetry::Unit::Bytes,
+                &format!("peer-{their_peer_id}-recv"),
+            )
+            .await;
+
+            let (mut last_sent, mut last_received) = (0, 0);
+            let mut ticker = tokio::time::interval(STATS_INTERVAL);
+
+            loop {
+                ticker.tick().await;
+
+                let (Some(peer_connection), Some(event_tx)) =
+                    (peer_connection.upgrade(), event_tx.upgrade())
+                else {
+                    break;
+                };
+
+                let stats = match peer_connection.stats().await {
+                    Ok(stats) => stats,
+                    Err(err) => {
+                        tracing::warn!(%err, "unable to get peer connection stats");
+                        continue;
+                    }
+                };
+
+                // NOTE(emily): The counters only go up, so feed them whatever is new.
+                if let Some(pair) = &stats.selected_candidate_pair {
+                    let sent = pair.bytes_sent.unwrap_or(last_sent);
+                    let received = pair.bytes_received.unwrap_or(last_received);
+                    sent_counter.update(sent.saturating_sub(last_sent) as usize);
+                    recv_counter.update(received.saturating_sub(last_received) as usize);
+                    (last_sent, last_received) = (sent, received);
+                }
+
+                if event_tx.send(PeerEvent::Stats(stats)).await.is_err() {
+                    break;
+                }
+            }
+        }
+        .instrument(span)
+    });
+
     peer_connection.offer(controlling).await?;
 
     Ok((control_tx, event_rx))
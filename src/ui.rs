@@ -5,7 +5,7 @@ use crate::logic::{Mode, PeerStreamRequest, PeerStreamRequestResponse};
 use crate::player::video::NV12TextureRender;
 
 use core::time;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
 
@@ -26,7 +26,7 @@ use crate::peer::{PeerControl, PeerError, PeerEvent};
 use eyre::Result;
 use media::dx::create_device_and_swapchain;
 
-use signal::{ConnectionId, PeerId};
+use signal::{ConnectionId, InviteToken, PeerId, RoomId};
 use signal::{SignallingControl, SignallingEvent};
 
 use tokio::sync::{mpsc, oneshot, Mutex, MutexGuard};
@@ -86,19 +86,23 @@ impl RemotePeer {
     #[tracing::instrument(skip(app_event_tx, signalling_control))]
     async fn connected(
         controlling: bool,
+        rtc_config: rtc::RtcConfig,
         signalling_control: mpsc::Sender<SignallingControl>,
         app_event_tx: mpsc::Sender<AppEvent>,
         our_peer_id: PeerId,
         their_peer_id: PeerId,
+        permission: signal::Permission,
     ) -> Result<Self> {
         let config = config::Config::load();
 
         let (control, event) = crate::peer::peer(
             config.webrtc_api,
+            rtc_config,
             our_peer_id.clone(),
             their_peer_id.clone(),
             signalling_control.clone(),
             controlling,
+            permission,
         )
         .await?;
 
@@ -206,6 +210,20 @@ impl RemotePeer {
                         );
                     }
                 }
+                PeerEvent::Recovering => {
+                    tracing::info!(%their_peer_id, "connection to peer lost, recovering");
+                }
+                PeerEvent::Recovered => {
+                    tracing::info!(%their_peer_id, "connection to peer recovered");
+                }
+                PeerEvent::Stats(stats) => {
+                    app_event_tx
+                        .send(AppEvent::PeerStats(
+                            our_peer_id.clone(),
+                            (their_peer_id.clone(), stats),
+                        ))
+                        .await?;
+                }
                 PeerEvent::Error(PeerError::Closed) => {
                     tracing::info!("peer is done forever");
                     app_event_tx
@@ -378,9 +396,17 @@ struct _Peer {
     last_connection_request: Option<String>,
     remote_peers: HashMap<PeerId, RemotePeer>,
     connection_peer_id: HashMap<ConnectionId, PeerId>,
+    outgoing_connection_peer_id: HashMap<ConnectionId, PeerId>,
     signal_control: mpsc::Sender<SignallingControl>,
     app_event_tx: mpsc::Sender<AppEvent>,
     peer_tasks: tokio::task::JoinSet<Result<()>>,
+    rtc_config: rtc::RtcConfig,
+    hosted_rooms: HashSet<RoomId>,
+    /// Rooms that we are in, with their host.
+    joined_rooms: HashMap<RoomId, PeerId>,
+    /// What peers that connected with one of our invites are allowed to do, anyone else was
+    /// accepted by hand.
+    peer_permissions: HashMap<PeerId, signal::Permission>,
 }
 
 impl std::fmt::Debug for _Peer {
@@ -390,14 +416,65 @@ impl std::fmt::Debug for _Peer {
             .field("last_connection_request", &self.last_connection_request)
             .field("remote_peers", &self.remote_peers)
             .field("connection_peer_id", &self.connection_peer_id)
+            .field(
+                "outgoing_connection_peer_id",
+                &self.outgoing_connection_peer_id,
+            )
             // .field("signal_control", &self.signal_control)
             // .field("app_event_tx", &self.app_event_tx)
             .field("peer_tasks", &self.peer_tasks)
+            .field("hosted_rooms", &self.hosted_rooms)
+            .field("joined_rooms", &self.joined_rooms)
+            .field("peer_permissions", &self.peer_permissions)
             .finish()
     }
 }
 
-impl _Peer {}
+impl _Peer {
+    /// Start a connection to `their_peer_id` and let the app know about it.
+    async fn add_remote_peer(&mut self, controlling: bool, their_peer_id: PeerId) -> Result<()> {
+        let remote_peer = RemotePeer::connected(
+            controlling,
+            self.rtc_config.clone(),
+            self.signal_control.clone(),
+            self.app_event_tx.clone(),
+            self.our_peer_id.clone(),
+            their_peer_id.clone(),
+            self.peer_permissions
+                .get(&their_peer_id)
+                .copied()
+                .unwrap_or_default(),
+        )
+        .await?;
+
+        let control = remote_peer.control.clone();
+
+        self.remote_peers.insert(their_peer_id.clone(), remote_peer);
+
+        self.app_event_tx
+            .send(AppEvent::RemotePeerConnected(
+                self.our_peer_id.clone(),
+                (their_peer_id, control),
+            ))
+            .await
+            .unwrap();
+
+        Ok(())
+    }
+
+    /// Start a controlling connection to `their_peer_id` once the signalling loop lets go of us.
+    fn spawn_controlling_peer(&mut self, zelf: Weak<Mutex<_Peer>>, their_peer_id: PeerId) {
+        self.peer_tasks.spawn(async move {
+            if let Some(zelf) = zelf.upgrade() {
+                zelf.lock()
+                    .await
+                    .add_remote_peer(true, their_peer_id)
+                    .await?;
+            }
+            eyre::Ok(())
+        });
+    }
+}
 
 #[derive(Clone, Deref, DerefMut, Debug)]
 struct UIPeer(
@@ -413,12 +490,14 @@ impl UIPeer {
     }
 
     /// Create a new peer, blocking waiting for a connection to the signalling server and the id of this peer.
-    #[tracing::instrument(skip(app_event_tx))]
-    async fn new<S: AsRef<str> + std::fmt::Debug>(
-        signalling_address: S,
+    #[tracing::instrument(skip(transport, app_event_tx))]
+    async fn new(
+        transport: &dyn signal::SignallingTransport,
+        options: signal::ClientOptions,
         app_event_tx: mpsc::Sender<AppEvent>,
     ) -> Result<Self> {
-        let (control, mut event_rx) = signal::client(signalling_address.as_ref()).await?;
+        let (control, mut event_rx, features) = transport.connect(options).await?;
+        tracing::debug!(?features, "signalling features");
 
         // TODO(emily): Hold onto other events that might turn up here, right now we just THROW them away,
         // not very nice.
@@ -446,18 +525,25 @@ impl UIPeer {
                 last_connection_request: None,
                 remote_peers: Default::default(),
                 connection_peer_id: Default::default(),
+                outgoing_connection_peer_id: Default::default(),
                 signal_control: control.clone(),
                 app_event_tx: app_event_tx,
                 peer_tasks: Default::default(),
+                rtc_config: Config::load().rtc.clone(),
+                hosted_rooms: Default::default(),
+                joined_rooms: Default::default(),
+                peer_permissions: Default::default(),
             })),
         );
 
         zelf.inner().await.peer_tasks.spawn({
             let weak_self = zelf.weak();
-            async move { Self::signalling(weak_self, event_rx, control.clone()).await }
-                .in_current_span()
+            let control = control.clone();
+            async move { Self::signalling(weak_self, event_rx, control).await }.in_current_span()
         });
 
+        control.send(SignallingControl::ListPeers).await?;
+
         Ok(zelf)
     }
 
@@ -483,10 +569,15 @@ impl UIPeer {
             let _guard = span.enter();
 
             match event {
-                signal::SignallingEvent::Id(_id) => {
-                    unreachable!("We should only ever get our peer_id once");
+                signal::SignallingEvent::Id(id) => {
+                    // NOTE(emily): Only after reconnecting as a new peer, everyone else sees the
+                    // new id but this window keeps the old one.
+                    tracing::warn!(%id, "signal server gave us a new id");
                 }
-                signal::SignallingEvent::ConectionRequest(peer_id, connection_id) => {
+                signal::SignallingEvent::ConectionRequest(peer_id, connection_id)
+                | signal::SignallingEvent::RoomJoinRequest(_, peer_id, connection_id) => {
+                    // NOTE(emily): Accepting a request to join one of our rooms is the same as
+                    // accepting any other connection, they connect to us either way.
                     tracing::info!(%peer_id, ?connection_id, "connection request");
                     zelf.last_connection_request = Some(connection_id.to_string());
                     zelf.connection_peer_id
@@ -501,6 +592,28 @@ impl UIPeer {
                         ))
                         .await;
                 }
+                signal::SignallingEvent::ConnectionRequested(peer_id, connection_id) => {
+                    tracing::info!(%peer_id, ?connection_id, "connection requested");
+                    zelf.outgoing_connection_peer_id
+                        .insert(connection_id, peer_id);
+                }
+                signal::SignallingEvent::ConnectionRequestClosed(
+                    peer_id,
+                    connection_id,
+                    reason,
+                ) => {
+                    tracing::info!(%peer_id, ?connection_id, ?reason, "connection request closed");
+                    zelf.connection_peer_id.remove(&connection_id);
+                    zelf.outgoing_connection_peer_id.remove(&connection_id);
+
+                    let _ = zelf
+                        .app_event_tx
+                        .send(AppEvent::ConnectionRequestClosed(
+                            zelf.our_peer_id.clone(),
+                            (connection_id, peer_id, reason),
+                        ))
+                        .await;
+                }
                 signal::SignallingEvent::Offer(peer_id, offer) => {
                     tracing::info!(%peer_id, offer, "offer");
 
@@ -557,41 +670,153 @@ impl UIPeer {
                     assert!(peer_id != our_peer_id);
 
                     tracing::info!(%peer_id, ?connection_id, "connection accepted");
+                    zelf.outgoing_connection_peer_id.remove(&connection_id);
 
-                    zelf.peer_tasks.spawn({
-                        let our_peer_id = our_peer_id;
-                        let their_peer_id = peer_id.clone();
-                        let tx = signal_tx.clone();
-                        let zelf = Arc::downgrade(&strong_zelf);
+                    zelf.spawn_controlling_peer(Arc::downgrade(&strong_zelf), peer_id);
+                }
+                signal::SignallingEvent::ConnectionRejected(peer_id, connection_id, reason) => {
+                    tracing::info!(%peer_id, ?connection_id, ?reason, "connection rejected");
+                    zelf.outgoing_connection_peer_id.remove(&connection_id);
 
-                        async move {
-                            if let Some(zelf) = zelf.upgrade() {
-                                let mut zelf = zelf.lock().await;
-
-                                let remote_peer = RemotePeer::connected(
-                                    true,
-                                    tx,
-                                    zelf.app_event_tx.clone(),
-                                    our_peer_id,
-                                    their_peer_id.clone(),
-                                )
-                                .await?;
-
-                                let control = remote_peer.control.clone();
-
-                                zelf.remote_peers.insert(their_peer_id.clone(), remote_peer);
-
-                                zelf.app_event_tx
-                                    .send(AppEvent::RemotePeerConnected(
-                                        zelf.our_peer_id.clone(),
-                                        (their_peer_id, control),
-                                    ))
-                                    .await
-                                    .unwrap();
-                            }
-                            eyre::Ok(())
+                    let _ = zelf
+                        .app_event_tx
+                        .send(AppEvent::ConnectionRejected(
+                            zelf.our_peer_id.clone(),
+                            (peer_id, reason),
+                        ))
+                        .await;
+                }
+                signal::SignallingEvent::Peers(peers) => {
+                    tracing::info!(?peers, "peers");
+
+                    let _ = zelf
+                        .app_event_tx
+                        .send(AppEvent::PeerDirectory(zelf.our_peer_id.clone(), peers))
+                        .await;
+                }
+                signal::SignallingEvent::PeerJoined(peer_info) => {
+                    tracing::info!(?peer_info, "peer joined");
+
+                    let _ = zelf
+                        .app_event_tx
+                        .send(AppEvent::PeerJoined(zelf.our_peer_id.clone(), peer_info))
+                        .await;
+                }
+                signal::SignallingEvent::PeerLeft(peer_id) => {
+                    tracing::info!(%peer_id, "peer left");
+
+                    let _ = zelf
+                        .app_event_tx
+                        .send(AppEvent::PeerLeft(zelf.our_peer_id.clone(), peer_id))
+                        .await;
+                }
+                signal::SignallingEvent::IceServers(ice_servers) => {
+                    tracing::info!(count = ice_servers.len(), "ice servers");
+
+                    zelf.rtc_config.ice_servers = Config::load()
+                        .rtc
+                        .ice_servers
+                        .iter()
+                        .cloned()
+                        .chain(ice_servers.into_iter().map(|ice_server| rtc::IceServer {
+                            urls: ice_server.urls,
+                            username: ice_server.username,
+                            credential: ice_server.credential,
+                        }))
+                        .collect();
+                }
+                signal::SignallingEvent::RoomCreated(room_id) => {
+                    tracing::info!(%room_id, "room created");
+                    zelf.hosted_rooms.insert(room_id);
+                }
+                signal::SignallingEvent::RoomJoinRequested(room_id, connection_id) => {
+                    tracing::info!(%room_id, ?connection_id, "room join requested");
+                }
+                signal::SignallingEvent::RoomJoined(room_id, host) => {
+                    // NOTE(emily): We asked to join so we are controlling, just like if we had
+                    // asked the host for a connection.
+                    tracing::info!(%room_id, %host, "room joined");
+                    zelf.joined_rooms.insert(room_id, host.clone());
+
+                    if !zelf.remote_peers.contains_key(&host) {
+                        zelf.spawn_controlling_peer(Arc::downgrade(&strong_zelf), host);
+                    }
+                }
+                signal::SignallingEvent::RoomMemberJoined(room_id, peer_info) => {
+                    tracing::info!(%room_id, ?peer_info, "room member joined");
+
+                    // NOTE(emily): Members only connect to the host, and their offer is on its
+                    // way so the remote peer has to exist before we handle the next event. If
+                    // we approved them then it already does.
+                    if zelf.hosted_rooms.contains(&room_id)
+                        && !zelf.remote_peers.contains_key(&peer_info.peer_id)
+                    {
+                        zelf.add_remote_peer(false, peer_info.peer_id).await?;
+                    }
+                }
+                signal::SignallingEvent::RoomMemberLeft(room_id, peer_id) => {
+                    tracing::info!(%room_id, %peer_id, "room member left");
+
+                    if zelf.hosted_rooms.contains(&room_id) {
+                        zelf.remote_peers.remove(&peer_id);
+                        zelf.peer_permissions.remove(&peer_id);
+                    }
+                }
+                signal::SignallingEvent::RoomInvitation(room_id, host) => {
+                    tracing::info!(%room_id, %host, "room invitation");
+
+                    let _ = zelf
+                        .app_event_tx
+                        .send(AppEvent::RoomInvitation(
+                            zelf.our_peer_id.clone(),
+                            (room_id, host),
+                        ))
+                        .await;
+                }
+                signal::SignallingEvent::RoomClosed(room_id) => {
+                    tracing::info!(%room_id, "room closed");
+
+                    if let Some(host) = zelf.joined_rooms.remove(&room_id) {
+                        zelf.remote_peers.remove(&host);
+                    }
+                }
+                signal::SignallingEvent::InviteCreated(_invite, ttl) => {
+                    tracing::info!(?ttl, "invite created");
+                }
+                signal::SignallingEvent::InvitedConnection(peer_id, connection_id, permission) => {
+                    // NOTE(emily): The server accepted this for us, and they are about to send
+                    // an offer.
+                    tracing::info!(%peer_id, ?connection_id, ?permission, "invited connection");
+                    zelf.peer_permissions.insert(peer_id.clone(), permission);
+
+                    if !zelf.remote_peers.contains_key(&peer_id) {
+                        zelf.add_remote_peer(false, peer_id).await?;
+                    }
+                }
+                signal::SignallingEvent::ConnectionState(state) => {
+                    tracing::info!(?state, "signalling connection state");
+
+                    if let signal::ConnectionState::Reconnected { resumed } = state {
+                        if !resumed {
+                            // NOTE(emily): The server forgot about us, along with any connection
+                            // requests and rooms that we had.
+                            zelf.connection_peer_id.clear();
+                            zelf.outgoing_connection_peer_id.clear();
+                            zelf.last_connection_request = None;
+                            zelf.hosted_rooms.clear();
+                            zelf.joined_rooms.clear();
                         }
-                    });
+
+                        signal_tx.send(SignallingControl::ListPeers).await?;
+                    }
+
+                    let _ = zelf
+                        .app_event_tx
+                        .send(AppEvent::SignallingConnectionState(
+                            zelf.our_peer_id.clone(),
+                            state,
+                        ))
+                        .await;
                 }
                 signal::SignallingEvent::Error(error) => {
                     tracing::info!("signalling error {error:?}");
@@ -609,15 +834,49 @@ impl UIPeer {
         Ok(zelf.connection_peer_id.clone())
     }
 
-    async fn connect(&self, peer_id: PeerId) -> Result<()> {
+    async fn list_peers(&self) -> Result<()> {
         let zelf = self.inner().await;
 
         Ok(zelf
             .signal_control
-            .send(SignallingControl::RequestConnection(peer_id))
+            .send(SignallingControl::ListPeers)
             .await?)
     }
 
+    async fn connect(&self, peer_id: PeerId, invite: Option<InviteToken>) -> Result<()> {
+        let signal_control = self.inner().await.signal_control.clone();
+        let invited = invite.is_some();
+
+        match signal::request_connection_with_invite(&signal_control, peer_id.clone(), invite).await
+        {
+            // NOTE(emily): An invite is accepted straight away, so there is nothing pending to
+            // cancel.
+            Ok(connection_id) if invited => {
+                tracing::info!(%peer_id, ?connection_id, "connection accepted by invite");
+            }
+            Ok(connection_id) => {
+                tracing::info!(%peer_id, ?connection_id, "connection requested");
+                self.inner()
+                    .await
+                    .outgoing_connection_peer_id
+                    .insert(connection_id, peer_id);
+            }
+            Err(err) => {
+                tracing::info!(%peer_id, %err, "connection request failed");
+                let zelf = self.inner().await;
+                let _ = zelf
+                    .app_event_tx
+                    .send(AppEvent::ConnectionRequestFailed(
+                        zelf.our_peer_id.clone(),
+                        (peer_id, err),
+                    ))
+                    .await;
+            }
+        }
+
+        Ok(())
+    }
+
     async fn accept_connection(&self, peer_id: &PeerId) -> Result<()> {
         // TODO(emily): Store a peer-id to connection id relation aswell.
         let connection_id = (async move {
@@ -637,30 +896,37 @@ impl UIPeer {
         if let Some(connection_id) = connection_id {
             let their_peer_id = zelf.connection_peer_id.remove(&connection_id).unwrap();
 
-            let peer = RemotePeer::connected(
-                false,
-                zelf.signal_control.clone(),
-                zelf.app_event_tx.clone(),
-                zelf.our_peer_id.clone(),
-                their_peer_id.clone(),
-            )
-            .await?;
+            zelf.add_remote_peer(false, their_peer_id).await?;
 
-            let control = peer.control.clone();
+            Ok(zelf
+                .signal_control
+                .send(SignallingControl::AcceptConnection(connection_id.clone()))
+                .await?)
+        } else {
+            tracing::warn!(
+                %zelf.our_peer_id,
+                %peer_id,
+                ?zelf.connection_peer_id,
+                "no incoming connection request from peer"
+            );
+            Err(eyre::eyre!("no incoming connection request from peer"))
+        }
+    }
 
-            zelf.remote_peers.insert(their_peer_id.clone(), peer);
+    async fn reject_connection(&self, peer_id: &PeerId) -> Result<()> {
+        let mut zelf = self.inner().await;
 
-            zelf.app_event_tx
-                .send(AppEvent::RemotePeerConnected(
-                    zelf.our_peer_id.clone(),
-                    (their_peer_id, control),
-                ))
-                .await
-                .unwrap();
+        let connection_id = zelf
+            .connection_peer_id
+            .iter()
+            .find_map(|(connection_id, id)| (id == peer_id).then(|| connection_id.clone()));
+
+        if let Some(connection_id) = connection_id {
+            zelf.connection_peer_id.remove(&connection_id);
 
             Ok(zelf
                 .signal_control
-                .send(SignallingControl::AcceptConnection(connection_id.clone()))
+                .send(SignallingControl::RejectConnection(connection_id))
                 .await?)
         } else {
             tracing::warn!(
@@ -673,6 +939,89 @@ impl UIPeer {
         }
     }
 
+    async fn cancel_connection(&self, peer_id: &PeerId) -> Result<()> {
+        let mut zelf = self.inner().await;
+
+        let connection_id = zelf
+            .outgoing_connection_peer_id
+            .iter()
+            .find_map(|(connection_id, id)| (id == peer_id).then(|| connection_id.clone()));
+
+        if let Some(connection_id) = connection_id {
+            zelf.outgoing_connection_peer_id.remove(&connection_id);
+
+            Ok(zelf
+                .signal_control
+                .send(SignallingControl::CancelConnection(connection_id))
+                .await?)
+        } else {
+            tracing::warn!(
+                %zelf.our_peer_id,
+                %peer_id,
+                ?zelf.outgoing_connection_peer_id,
+                "no outgoing connection request to peer"
+            );
+            Err(eyre::eyre!("no outgoing connection request to peer"))
+        }
+    }
+
+    async fn create_invite(&self, permission: signal::Permission) -> Result<()> {
+        let signal_control = self.inner().await.signal_control.clone();
+
+        let (invite, ttl) = signal::create_invite(
+            &signal_control,
+            signal::InviteOptions {
+                permission,
+                ..Default::default()
+            },
+        )
+        .await?;
+        tracing::info!(?permission, ?ttl, "invite created");
+
+        let zelf = self.inner().await;
+        let _ = zelf
+            .app_event_tx
+            .send(AppEvent::InviteCreated(zelf.our_peer_id.clone(), invite))
+            .await;
+
+        Ok(())
+    }
+
+    async fn create_room(&self, policy: signal::JoinPolicy) -> Result<()> {
+        let signal_control = self.inner().await.signal_control.clone();
+
+        let room_id = signal::create_room(&signal_control, policy).await?;
+        tracing::info!(%room_id, ?policy, "room created");
+
+        let mut zelf = self.inner().await;
+        zelf.hosted_rooms.insert(room_id.clone());
+        let _ = zelf
+            .app_event_tx
+            .send(AppEvent::RoomCreated(zelf.our_peer_id.clone(), room_id))
+            .await;
+
+        Ok(())
+    }
+
+    async fn join_room(&self, room_id: RoomId) -> Result<()> {
+        let signal_control = self.inner().await.signal_control.clone();
+
+        // NOTE(emily): Once we are in, the signalling loop connects us to the host.
+        match signal::join_room(&signal_control, room_id.clone()).await {
+            Ok(signal::RoomJoin::Joined(host)) => {
+                tracing::info!(%room_id, %host, "joined room");
+            }
+            Ok(signal::RoomJoin::Pending(connection_id)) => {
+                tracing::info!(%room_id, ?connection_id, "waiting for the host to let us in");
+            }
+            Err(err) => {
+                tracing::info!(%room_id, %err, "unable to join room");
+            }
+        }
+
+        Ok(())
+    }
+
     async fn request_stream(
         &self,
         peer_id: PeerId,
@@ -733,6 +1082,18 @@ struct PeerMediaState {
 struct PeerWindowState {
     visible: Visible,
     connect_peer_id: String,
+    room_id: String,
+    room_policy: signal::JoinPolicy,
+    hosted_room: Option<RoomId>,
+    invite_permission: signal::Permission,
+    /// The last invite that we made, as `peer_id:token` to paste into the connect box.
+    invite: Option<String>,
+    /// Set when there is no signal server and the user carries signalling between peers by hand.
+    manual_signalling: Option<signal::ManualTransport>,
+    manual_paste: String,
+    signalling_state: Option<signal::ConnectionState>,
+    online_peers: HashMap<PeerId, signal::PeerMetadata>,
+    outgoing_connection_requests: HashSet<PeerId>,
     connection_requests: HashMap<ConnectionId, PeerId>,
     connected_peers: HashMap<PeerId, mpsc::Sender<PeerControl>>,
     stream_request: PeerStreamRequest,
@@ -754,6 +1115,34 @@ struct PeerWindowState {
         ),
     >,
     peer_statistics_average: HashMap<PeerId, VecDeque<Statistics>>,
+    peer_rtc_stats: HashMap<PeerId, rtc::RtcStats>,
+}
+
+/// How the connection to a peer is doing, underneath the media statistics.
+fn rtc_stats_ui(ui: &mut egui::Ui, stats: &rtc::RtcStats) {
+    ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
+
+    if let Some(round_trip_time) = stats.round_trip_time {
+        ui.label(format!("{:8}ms round trip", round_trip_time.as_millis()));
+        ui.end_row();
+    }
+
+    if let Some(pair) = &stats.selected_candidate_pair {
+        ui.label(format!("{} -> {}", pair.local, pair.remote));
+        ui.end_row();
+        if let (Some(sent), Some(received)) = (pair.bytes_sent, pair.bytes_received) {
+            ui.label(format!("{sent:12} bytes sent {received:12} bytes received"));
+            ui.end_row();
+        }
+    }
+
+    for channel in &stats.channels {
+        ui.label(format!(
+            "{:12} bytes sent {:12} bytes received on {}",
+            channel.bytes_sent, channel.bytes_received, channel.label
+        ));
+        ui.end_row();
+    }
 }
 
 enum ShouldRemove {
@@ -891,18 +1280,170 @@ impl PeerWindowState {
     fn window_ui(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, peer: &UIPeer) {
         let config = Config::load();
 
-        ui.text_edit_singleline(&mut self.connect_peer_id);
+        match self.signalling_state {
+            Some(signal::ConnectionState::Reconnecting { attempt }) => {
+                ui.label(format!("reconnecting to signal server (attempt {attempt})"));
+                ui.end_row();
+            }
+            Some(signal::ConnectionState::ServerShuttingDown { .. }) => {
+                ui.label("signal server restarting");
+                ui.end_row();
+            }
+            Some(signal::ConnectionState::Disconnected) => {
+                ui.label("disconnected from signal server");
+                ui.end_row();
+            }
+            Some(signal::ConnectionState::Reconnected { .. }) | None => {}
+        }
+
+        if let Some(manual_signalling) = &self.manual_signalling {
+            let mut outgoing = manual_signalling.outgoing().borrow().clone();
+            ui.text_edit_singleline(&mut outgoing)
+                .on_hover_text("send this to the other side, again whenever it changes");
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.manual_paste)
+                    .on_hover_text("what the other side sent");
+                if ui.button("paste").clicked() {
+                    tokio::spawn({
+                        let manual_signalling = manual_signalling.clone();
+                        let text = std::mem::take(&mut self.manual_paste);
+                        async move {
+                            if let Err(err) = manual_signalling.paste(&text).await {
+                                tracing::error!(%err, "unable to paste signalling");
+                            }
+                        }
+                    });
+                }
+            });
+            ui.end_row();
+        }
+
+        ui.text_edit_singleline(&mut self.connect_peer_id)
+            .on_hover_text("a peer id, or an invite as peer_id:token");
         if ui.button("connect").clicked() {
             tokio::spawn({
-                let connect_peer_id: PeerId = self.connect_peer_id.clone().into();
+                let (connect_peer_id, invite) = match self.connect_peer_id.trim().split_once(':') {
+                    Some((peer_id, invite)) => (
+                        PeerId::from(peer_id.to_owned()),
+                        Some(InviteToken::from(invite.to_owned())),
+                    ),
+                    None => (PeerId::from(self.connect_peer_id.trim().to_owned()), None),
+                };
+                self.outgoing_connection_requests
+                    .insert(connect_peer_id.clone());
                 let peer = peer.clone();
                 async move {
-                    peer.connect(connect_peer_id.clone()).await.unwrap();
+                    peer.connect(connect_peer_id.clone(), invite).await.unwrap();
                 }
             });
         }
         ui.end_row();
 
+        ui.horizontal(|ui| {
+            for (permission, label) in [
+                (signal::Permission::ViewOnly, "view only"),
+                (signal::Permission::Control, "control"),
+            ] {
+                ui.selectable_value(&mut self.invite_permission, permission, label);
+            }
+            if ui.button("create invite").clicked() {
+                tokio::spawn({
+                    let permission = self.invite_permission;
+                    let peer = peer.clone();
+                    async move {
+                        peer.create_invite(permission).await.unwrap();
+                    }
+                });
+            }
+        });
+        if let Some(invite) = &mut self.invite {
+            ui.text_edit_singleline(invite)
+                .on_hover_text("share this with whoever should connect");
+        }
+        ui.end_row();
+
+        ui.horizontal(|ui| {
+            for (policy, label) in [
+                (signal::JoinPolicy::AutoAccept, "anyone"),
+                (signal::JoinPolicy::Approve, "approve"),
+                (signal::JoinPolicy::InviteOnly, "invite only"),
+            ] {
+                ui.selectable_value(&mut self.room_policy, policy, label);
+            }
+            if ui.button("host room").clicked() {
+                tokio::spawn({
+                    let policy = self.room_policy;
+                    let peer = peer.clone();
+                    async move {
+                        peer.create_room(policy).await.unwrap();
+                    }
+                });
+            }
+        });
+        if let Some(room_id) = &self.hosted_room {
+            ui.label(format!("hosting room {room_id}"));
+        }
+        ui.end_row();
+
+        ui.text_edit_singleline(&mut self.room_id);
+        if ui.button("join room").clicked() {
+            tokio::spawn({
+                let room_id: RoomId = self.room_id.trim().to_owned().into();
+                let peer = peer.clone();
+                async move {
+                    peer.join_room(room_id).await.unwrap();
+                }
+            });
+        }
+        ui.end_row();
+
+        ui.horizontal(|ui| {
+            ui.heading("Online Peers");
+            if ui.button("refresh").clicked() {
+                tokio::spawn({
+                    let peer = peer.clone();
+                    async move {
+                        peer.list_peers().await.unwrap();
+                    }
+                });
+            }
+        });
+        ui.end_row();
+
+        let mut online_peers: Vec<_> = self.online_peers.iter().collect();
+        online_peers.sort_by_key(|(peer_id, metadata)| {
+            (metadata.role != signal::PeerRole::Host, peer_id.to_string())
+        });
+
+        for (p_id, metadata) in online_peers {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} {} ({:?}) {}",
+                    p_id,
+                    metadata.display_name.as_deref().unwrap_or(""),
+                    metadata.role,
+                    metadata.capabilities.join(", "),
+                ));
+
+                if !self.connected_peers.contains_key(p_id)
+                    && !self.outgoing_connection_requests.contains(p_id)
+                    && ui.button("connect").clicked()
+                {
+                    self.outgoing_connection_requests.insert(p_id.clone());
+                    tokio::spawn({
+                        let peer = peer.clone();
+                        let p_id = p_id.clone();
+                        async move {
+                            peer.connect(p_id, None).await.unwrap();
+                        }
+                    });
+                }
+            });
+            ui.end_row();
+        }
+
         ui.heading("Connected Peers");
         ui.end_row();
 
@@ -914,6 +1455,10 @@ impl PeerWindowState {
 
                 ui.end_row();
 
+                if let Some(stats) = self.peer_rtc_stats.get(their_peer_id) {
+                    rtc_stats_ui(ui, stats);
+                }
+
                 enum MediaResult {
                     Done,
                     Empty(Option<PeerMediaState>),
@@ -1088,9 +1633,36 @@ impl PeerWindowState {
             });
         }
 
+        ui.heading("Outgoing Connection Requests");
+        ui.end_row();
+
+        let mut cancelled_connection_request = None;
+
+        for p_id in &self.outgoing_connection_requests {
+            ui.label(format!("{} (waiting)", p_id));
+
+            if ui.button("cancel").clicked() {
+                cancelled_connection_request = Some(p_id.clone());
+                tokio::spawn({
+                    let peer = peer.clone();
+                    let p_id = p_id.clone();
+                    async move {
+                        peer.cancel_connection(&p_id).await.unwrap();
+                    }
+                });
+            }
+            ui.end_row();
+        }
+
+        if let Some(p_id) = cancelled_connection_request {
+            self.outgoing_connection_requests.remove(&p_id);
+        }
+
         ui.heading("Connection Requests");
         ui.end_row();
 
+        let mut rejected_connection_request = None;
+
         for (c_id, p_id) in &self.connection_requests {
             ui.label(format!("{}", p_id));
             ui.label(format!("{}", c_id));
@@ -1104,9 +1676,23 @@ impl PeerWindowState {
                     }
                 });
             }
+            if ui.button("reject").clicked() {
+                rejected_connection_request = Some(c_id.clone());
+                tokio::spawn({
+                    let peer = peer.clone();
+                    let p_id = p_id.clone();
+                    async move {
+                        peer.reject_connection(&p_id).await.unwrap();
+                    }
+                });
+            }
             ui.end_row();
         }
 
+        if let Some(connection_id) = rejected_connection_request {
+            self.connection_requests.remove(&connection_id);
+        }
+
         ui.heading("Stream Requests");
         ui.end_row();
 
@@ -1193,6 +1779,17 @@ impl std::fmt::Debug for PeerWindowState {
         f.debug_struct("PeerWindowState")
             .field("visible", &self.visible)
             .field("connect_peer_id", &self.connect_peer_id)
+            .field("room_id", &self.room_id)
+            .field("room_policy", &self.room_policy)
+            .field("hosted_room", &self.hosted_room)
+            .field("invite_permission", &self.invite_permission)
+            .field("manual_signalling", &self.manual_signalling.is_some())
+            .field("signalling_state", &self.signalling_state)
+            .field("online_peers", &self.online_peers)
+            .field(
+                "outgoing_connection_requests",
+                &self.outgoing_connection_requests,
+            )
             .field("connection_requests", &self.connection_requests)
             .field("connected_peers", &self.connected_peers)
             .field("stream_requests", &self.stream_requests)
@@ -1202,9 +1799,16 @@ impl std::fmt::Debug for PeerWindowState {
 }
 
 enum AppEvent {
-    Peer(UIPeer),
+    Peer(UIPeer, Option<signal::ManualTransport>),
     ConnectionRequest(PeerId, (ConnectionId, PeerId)),
+    ConnectionRejected(PeerId, (PeerId, signal::RejectionReason)),
+    ConnectionRequestFailed(PeerId, (PeerId, signal::SignallingError)),
+    ConnectionRequestClosed(PeerId, (ConnectionId, PeerId, signal::RequestClosedReason)),
     RemotePeerConnected(PeerId, (PeerId, mpsc::Sender<PeerControl>)),
+    PeerDirectory(PeerId, Vec<signal::PeerInfo>),
+    PeerJoined(PeerId, signal::PeerInfo),
+    PeerLeft(PeerId, PeerId),
+    SignallingConnectionState(PeerId, signal::ConnectionState),
     RemotePeerStreamRequest(
         PeerId,
         (
@@ -1218,9 +1822,15 @@ enum AppEvent {
         (PeerId, mpsc::Receiver<media::decoder::DecoderEvent>),
     ),
     PeerClosed(PeerId, PeerId),
+    PeerStats(PeerId, (PeerId, rtc::RtcStats)),
+    RoomCreated(PeerId, RoomId),
+    RoomInvitation(PeerId, (RoomId, PeerId)),
+    InviteCreated(PeerId, InviteToken),
 }
 
 struct App {
+    new_peer_id: String,
+    manual_signalling: bool,
     peers: HashMap<PeerId, (PeerWindowState, UIPeer)>,
     event_rx: mpsc::Receiver<AppEvent>,
     event_tx: mpsc::Sender<AppEvent>,
@@ -1230,6 +1840,8 @@ struct App {
 impl std::fmt::Debug for App {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("App")
+            .field("new_peer_id", &self.new_peer_id)
+            .field("manual_signalling", &self.manual_signalling)
             .field("peers", &self.peers)
             // .field("event_rx", &self.event_rx)
             // .field("event_tx", &self.event_tx)
@@ -1242,6 +1854,8 @@ impl Default for App {
         let (event_tx, event_rx) = mpsc::channel(10);
 
         Self {
+            new_peer_id: Config::load().signal_peer_id.clone().unwrap_or_default(),
+            manual_signalling: false,
             peers: Default::default(),
             event_rx,
             event_tx,
@@ -1271,15 +1885,50 @@ impl App {
         let ui = egui::Window::new("App");
 
         ui.show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("id");
+                ui.text_edit_singleline(&mut self.new_peer_id)
+                    .on_hover_text("leave empty for a random id");
+            });
+            ui.checkbox(&mut self.manual_signalling, "manual signalling")
+                .on_hover_text("no signal server, copy and paste between the peers instead");
+
             if ui.button("new peer").clicked() {
                 tokio::spawn({
                     let event_tx = self.event_tx.clone();
+                    let peer_id = Some(self.new_peer_id.trim())
+                        .filter(|peer_id| !peer_id.is_empty())
+                        .map(|peer_id| PeerId::from(peer_id.to_owned()));
+                    let manual_signalling = self
+                        .manual_signalling
+                        .then(signal::ManualTransport::new);
                     async move {
                         let config = Config::load();
-                        let peer = UIPeer::new(&config.signal_server, event_tx.clone())
-                            .await
-                            .unwrap();
-                        event_tx.send(AppEvent::Peer(peer)).await.unwrap();
+                        let transport: Box<dyn signal::SignallingTransport> =
+                            match &manual_signalling {
+                                Some(manual_signalling) => Box::new(manual_signalling.clone()),
+                                None => {
+                                    Box::new(signal::WebsocketTransport::new(&config.signal_server))
+                                }
+                            };
+                        match UIPeer::new(
+                            transport.as_ref(),
+                            signal::ClientOptions {
+                                credentials: config.signal_credentials.clone(),
+                                peer_id,
+                                metadata: config.signal_metadata.clone(),
+                                ..Default::default()
+                            },
+                            event_tx.clone(),
+                        )
+                        .await
+                        {
+                            Ok(peer) => event_tx
+                                .send(AppEvent::Peer(peer, manual_signalling))
+                                .await
+                                .unwrap(),
+                            Err(err) => tracing::error!("unable to create peer {err}"),
+                        }
                     }
                 });
             }
@@ -1305,9 +1954,17 @@ impl App {
 
             if let Ok(event) = self.event_rx.try_recv() {
                 match event {
-                    AppEvent::Peer(peer) => {
-                        self.peers
-                            .insert(peer.our_id().clone(), (PeerWindowState::default(), peer));
+                    AppEvent::Peer(peer, manual_signalling) => {
+                        self.peers.insert(
+                            peer.our_id().clone(),
+                            (
+                                PeerWindowState {
+                                    manual_signalling,
+                                    ..Default::default()
+                                },
+                                peer,
+                            ),
+                        );
                     }
                     AppEvent::ConnectionRequest(our_peer_id, (connection_request_id, peer_id)) => {
                         if let Some((peer_window_state, _)) = self.peers.get_mut(&our_peer_id) {
@@ -1316,11 +1973,42 @@ impl App {
                                 .insert(connection_request_id, peer_id);
                         }
                     }
+                    AppEvent::ConnectionRejected(our_peer_id, (their_peer_id, reason)) => {
+                        tracing::info!(%our_peer_id, %their_peer_id, ?reason, "connection rejected");
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_peer_id) {
+                            peer_window_state
+                                .outgoing_connection_requests
+                                .remove(&their_peer_id);
+                        }
+                    }
+                    AppEvent::ConnectionRequestFailed(our_peer_id, (their_peer_id, err)) => {
+                        tracing::info!(%our_peer_id, %their_peer_id, %err, "connection request failed");
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_peer_id) {
+                            peer_window_state
+                                .outgoing_connection_requests
+                                .remove(&their_peer_id);
+                        }
+                    }
+                    AppEvent::ConnectionRequestClosed(
+                        our_peer_id,
+                        (connection_id, their_peer_id, reason),
+                    ) => {
+                        tracing::info!(%our_peer_id, %their_peer_id, ?reason, "connection request closed");
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_peer_id) {
+                            peer_window_state.connection_requests.remove(&connection_id);
+                            peer_window_state
+                                .outgoing_connection_requests
+                                .remove(&their_peer_id);
+                        }
+                    }
                     AppEvent::RemotePeerConnected(peer_id, (their_peer_id, control)) => {
                         if let Some((peer_window_state, _)) = self.peers.get_mut(&peer_id) {
                             peer_window_state
                                 .connected_peers
                                 .insert(their_peer_id.clone(), control);
+                            peer_window_state
+                                .outgoing_connection_requests
+                                .remove(&their_peer_id);
                         }
 
                         // If we had a connection request from this peer then we can get rid of it
@@ -1338,6 +2026,35 @@ impl App {
                             }
                         }
                     }
+                    AppEvent::PeerDirectory(our_id, peers) => {
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
+                            peer_window_state.online_peers = peers
+                                .into_iter()
+                                .map(|peer_info| (peer_info.peer_id, peer_info.metadata))
+                                .collect();
+                        }
+                    }
+                    AppEvent::PeerJoined(our_id, peer_info) => {
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
+                            peer_window_state
+                                .online_peers
+                                .insert(peer_info.peer_id, peer_info.metadata);
+                        }
+                    }
+                    AppEvent::PeerLeft(our_id, their_id) => {
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
+                            peer_window_state.online_peers.remove(&their_id);
+                        }
+                    }
+                    AppEvent::SignallingConnectionState(our_id, state) => {
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
+                            if state == (signal::ConnectionState::Reconnected { resumed: false }) {
+                                peer_window_state.connection_requests.clear();
+                                peer_window_state.outgoing_connection_requests.clear();
+                            }
+                            peer_window_state.signalling_state = Some(state);
+                        }
+                    }
                     AppEvent::RemotePeerStreamRequest(our_id, (their_id, request, response_tx)) => {
                         if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
                             peer_window_state
@@ -1361,6 +2078,30 @@ impl App {
                                 .expect("Expect remote PeerControl to exist when it goes away");
 
                             peer_window_state.connected_peer_media.remove(&their_id);
+                            peer_window_state.peer_rtc_stats.remove(&their_id);
+                        }
+                    }
+                    AppEvent::PeerStats(our_id, (their_id, stats)) => {
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
+                            if peer_window_state.connected_peers.contains_key(&their_id) {
+                                peer_window_state.peer_rtc_stats.insert(their_id, stats);
+                            }
+                        }
+                    }
+                    AppEvent::InviteCreated(our_id, invite) => {
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
+                            peer_window_state.invite = Some(format!("{our_id}:{invite}"));
+                        }
+                    }
+                    AppEvent::RoomCreated(our_id, room_id) => {
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
+                            peer_window_state.hosted_room = Some(room_id);
+                        }
+                    }
+                    AppEvent::RoomInvitation(our_id, (room_id, host)) => {
+                        tracing::info!(%our_id, %room_id, %host, "invited to room");
+                        if let Some((peer_window_state, _)) = self.peers.get_mut(&our_id) {
+                            peer_window_state.room_id = room_id.to_string();
                         }
                     }
                 }
@@ -4,7 +4,7 @@ use tracing::Instrument;
 use crate::{
     chunk::{assembly, chunk, AssemblyControl, Chunk},
     rtc::{ChannelControl, ChannelEvent, ChannelOptions, PeerConnection},
-    ARBITRARY_CHANNEL_LIMIT,
+    ARBITRARY_CHANNEL_LIMIT, VIDEO_CHANNEL_ID,
 };
 
 use media::VideoBuffer;
@@ -35,6 +35,7 @@ pub(crate) async fn video_channel(
             Some(ChannelOptions {
                 ordered: Some(false),
                 max_retransmits: Some(0),
+                negotiated: Some(VIDEO_CHANNEL_ID),
             }),
         )
         .await?;